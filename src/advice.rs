@@ -0,0 +1,25 @@
+/// The expected access pattern of a range, passed to `ReadAt::advise_range`.
+///
+/// # Platform-specific behavior
+///
+/// These correspond to the `POSIX_FADV_*` values of the `posix_fadvise`
+/// function.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Advice {
+    /// No particular access pattern, the default (`POSIX_FADV_NORMAL`).
+    Normal,
+    /// The range will be accessed sequentially, from lower to higher offsets
+    /// (`POSIX_FADV_SEQUENTIAL`).
+    Sequential,
+    /// The range will be accessed in random order (`POSIX_FADV_RANDOM`).
+    Random,
+    /// The range will be accessed soon (`POSIX_FADV_WILLNEED`).
+    WillNeed,
+    /// The range will not be accessed soon (`POSIX_FADV_DONTNEED`).
+    ///
+    /// On Linux, this drops the cached pages of the range that are not
+    /// dirty. Write the data back first to evict it entirely.
+    DontNeed,
+    /// The range will be accessed only once (`POSIX_FADV_NOREUSE`).
+    NoReuse,
+}
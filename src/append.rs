@@ -0,0 +1,334 @@
+use std::collections::BTreeMap;
+use std::error;
+use std::fmt;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use {lock_ignoring_poison, FileExt};
+
+/// Appends records to data shared by several threads, returning the offset
+/// each record was written at.
+///
+/// Opening a file with `O_APPEND` does not tell the writer where its data
+/// ended up, and seeking to the end before writing races with other
+/// writers. Instead, `Appender` reserves space for each record by
+/// atomically advancing an offset, then writes the record with
+/// `write_all_offset` at the reserved offset. Records appended concurrently
+/// never overlap, and the writes themselves proceed in parallel.
+///
+/// As writes may complete out of order, `Appender` also tracks the
+/// high-water mark: the offset below which all reserved space has been
+/// written. Data beyond it may still contain gaps that read as zeros.
+///
+/// If writing a record fails, its space stays reserved and the high-water
+/// mark cannot advance past it until the returned `AppendError` is passed to
+/// `retry` or `abandon`.
+///
+/// Appending through other means while an `Appender` is in use overwrites
+/// or is overwritten by its records.
+///
+/// ```
+/// use file_offset::Appender;
+/// use std::sync::Mutex;
+///
+/// let log = Appender::new(Mutex::new(b"header".to_vec())).unwrap();
+/// assert_eq!(log.append(b"first").unwrap(), 6);
+/// assert_eq!(log.append(b"second").unwrap(), 11);
+/// assert_eq!(log.high_water_mark(), 17);
+/// ```
+#[derive(Debug)]
+pub struct Appender<T> {
+    inner: T,
+    // The offset at which the next reservation starts.
+    next: AtomicU64,
+    high_water: AtomicU64,
+    // Written ranges beyond the high-water mark, mapping start to end.
+    written: Mutex<BTreeMap<u64, u64>>,
+}
+
+impl<T: FileExt> Appender<T> {
+    /// Creates an `Appender` appending at the current end of `inner`.
+    pub fn new(inner: T) -> io::Result<Appender<T>> {
+        let size = inner.size()?;
+        Ok(Appender::with_offset(inner, size))
+    }
+
+    /// Creates an `Appender` appending starting at `offset`.
+    ///
+    /// Everything before `offset` is considered written.
+    pub fn with_offset(inner: T, offset: u64) -> Appender<T> {
+        Appender {
+            inner,
+            next: AtomicU64::new(offset),
+            high_water: AtomicU64::new(offset),
+            written: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Appends `buf`, returning the offset it was written at.
+    ///
+    /// If the write fails, the space reserved for `buf` remains reserved
+    /// but unwritten, and the high-water mark does not advance past it until
+    /// the returned error is passed to `retry` or `abandon`.
+    pub fn append(&self, buf: &[u8]) -> Result<u64, AppendError> {
+        let len = buf.len() as u64;
+        let offset = self
+            .next
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |next| {
+                next.checked_add(len)
+            })
+            .map_err(|_| AppendError {
+                reserved: None,
+                error: io::Error::new(io::ErrorKind::InvalidInput, "append offset overflows u64"),
+            })?;
+        self.write(buf, offset)
+    }
+
+    /// Writes `buf` again into the space reserved for a record whose write
+    /// failed, returning the offset it was written at.
+    ///
+    /// If no space was reserved for the record, it is appended anew.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the length of `buf` differs from that of the failed record.
+    pub fn retry(&self, err: AppendError, buf: &[u8]) -> Result<u64, AppendError> {
+        match err.reserved {
+            Some((offset, len)) => {
+                assert_eq!(buf.len() as u64, len, "retried record differs in length");
+                self.write(buf, offset)
+            }
+            None => self.append(buf),
+        }
+    }
+
+    /// Gives up on a record whose write failed, returning the underlying
+    /// error.
+    ///
+    /// The space reserved for the record is considered written, allowing
+    /// the high-water mark to advance past it. Its contents are unspecified,
+    /// as the record may have been written partially; use
+    /// `WriteAt::zero_range` on `AppendError::offset` beforehand to clear
+    /// it.
+    pub fn abandon(&self, err: AppendError) -> io::Error {
+        if let Some((offset, len)) = err.reserved {
+            self.complete(offset, offset + len);
+        }
+        err.error
+    }
+
+    fn write(&self, buf: &[u8], offset: u64) -> Result<u64, AppendError> {
+        let len = buf.len() as u64;
+        if len == 0 {
+            return Ok(offset);
+        }
+        if let Err(e) = self.inner.write_all_offset(buf, offset) {
+            return Err(AppendError {
+                reserved: Some((offset, len)),
+                error: e.into_error(),
+            });
+        }
+        self.complete(offset, offset + len);
+        Ok(offset)
+    }
+
+    // Records that `[start, end)` has been written, advancing the
+    // high-water mark if it was the lowest unwritten range.
+    fn complete(&self, start: u64, mut end: u64) {
+        let mut written = lock_ignoring_poison(&self.written);
+        if start != self.high_water.load(Ordering::Relaxed) {
+            written.insert(start, end);
+            return;
+        }
+        while let Some(next) = written.remove(&end) {
+            end = next;
+        }
+        self.high_water.store(end, Ordering::Release);
+    }
+}
+
+impl<T> Appender<T> {
+    /// Returns the offset below which all appended data has been written.
+    ///
+    /// Reads below this offset observe the appended records. Whether the
+    /// data has reached stable storage depends on the underlying data.
+    pub fn high_water_mark(&self) -> u64 {
+        self.high_water.load(Ordering::Acquire)
+    }
+
+    /// Returns the offset at which the next record will be appended.
+    pub fn reserved(&self) -> u64 {
+        self.next.load(Ordering::Relaxed)
+    }
+
+    /// Returns a reference to the underlying data.
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    /// Consumes the `Appender`, returning the underlying data.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+/// The error returned by `Appender::append` if a record could not be
+/// written.
+///
+/// Unless reserving space for the record failed, the space stays reserved.
+/// Pass the error to `Appender::retry` to write the record again, or to
+/// `Appender::abandon` to give up on it. Otherwise, the high-water mark of
+/// the `Appender` never advances past the record.
+///
+/// For that reason, there is no `From<AppendError>` implementation for
+/// `io::Error`: converting the error implicitly with `?` would leave the
+/// space reserved forever. Use `abandon` to obtain the `io::Error` instead.
+///
+/// ```
+/// use file_offset::Appender;
+/// use std::io;
+/// use std::sync::Mutex;
+///
+/// fn log(appender: &Appender<Mutex<Vec<u8>>>, record: &[u8]) -> io::Result<u64> {
+///     appender.append(record).map_err(|e| appender.abandon(e))
+/// }
+///
+/// let appender = Appender::new(Mutex::new(Vec::new())).unwrap();
+/// assert_eq!(log(&appender, b"record").unwrap(), 0);
+/// ```
+#[derive(Debug)]
+pub struct AppendError {
+    // The offset and length of the space reserved for the record.
+    reserved: Option<(u64, u64)>,
+    error: io::Error,
+}
+
+impl AppendError {
+    /// Returns the offset of the space reserved for the record, or `None`
+    /// if no space was reserved.
+    pub fn offset(&self) -> Option<u64> {
+        self.reserved.map(|(offset, _)| offset)
+    }
+
+    /// Returns the kind of the underlying I/O error.
+    pub fn kind(&self) -> io::ErrorKind {
+        self.error.kind()
+    }
+
+    /// Returns a reference to the underlying I/O error.
+    pub fn error(&self) -> &io::Error {
+        &self.error
+    }
+}
+
+impl fmt::Display for AppendError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.reserved {
+            Some((offset, _)) => write!(f, "{} (appending at {})", self.error, offset),
+            None => self.error.fmt(f),
+        }
+    }
+}
+
+impl error::Error for AppendError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Mutex;
+
+    use super::Appender;
+    use {ReadAt, WriteAt};
+
+    // Data whose writes fail while `failing` is set.
+    #[derive(Default)]
+    struct Flaky {
+        data: Mutex<Vec<u8>>,
+        failing: AtomicBool,
+    }
+
+    impl ReadAt for Flaky {
+        fn read_offset(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+            self.data.read_offset(buf, offset)
+        }
+        fn size(&self) -> io::Result<u64> {
+            self.data.size()
+        }
+    }
+
+    impl WriteAt for Flaky {
+        fn write_offset(&self, buf: &[u8], offset: u64) -> io::Result<usize> {
+            if self.failing.load(Ordering::Relaxed) {
+                return Err(io::Error::other("injected"));
+            }
+            self.data.write_offset(buf, offset)
+        }
+        fn set_size(&self, size: u64) -> io::Result<()> {
+            self.data.set_size(size)
+        }
+    }
+
+    #[test]
+    fn out_of_order_completion() {
+        let log = Appender::with_offset(Mutex::new(Vec::new()), 0);
+        log.next.store(40, Ordering::Relaxed);
+        log.complete(20, 30);
+        log.complete(10, 20);
+        assert_eq!(log.high_water_mark(), 0);
+        log.complete(0, 10);
+        assert_eq!(log.high_water_mark(), 30);
+        log.complete(30, 40);
+        assert_eq!(log.high_water_mark(), 40);
+        assert!(log.written.into_inner().unwrap().is_empty());
+    }
+
+    #[test]
+    fn retry_failed_write() {
+        let log = Appender::new(Flaky::default()).unwrap();
+        log.get_ref().failing.store(true, Ordering::Relaxed);
+        let err = log.append(b"first").unwrap_err();
+        assert_eq!(err.offset(), Some(0));
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+        log.get_ref().failing.store(false, Ordering::Relaxed);
+        assert_eq!(log.append(b"second").unwrap(), 5);
+        assert_eq!(log.high_water_mark(), 0);
+        assert_eq!(log.retry(err, b"first").unwrap(), 0);
+        assert_eq!(log.high_water_mark(), 11);
+        assert_eq!(
+            *log.into_inner().data.into_inner().unwrap(),
+            *b"firstsecond"
+        );
+    }
+
+    #[test]
+    fn abandon_failed_write() {
+        let log = Appender::new(Flaky::default()).unwrap();
+        assert_eq!(log.append(b"first").unwrap(), 0);
+        log.get_ref().failing.store(true, Ordering::Relaxed);
+        let err = log.append(b"second").unwrap_err();
+        let retried = log.retry(err, b"second").unwrap_err();
+        assert_eq!(retried.offset(), Some(5));
+        log.get_ref().failing.store(false, Ordering::Relaxed);
+        assert_eq!(log.append(b"third").unwrap(), 11);
+        assert_eq!(log.high_water_mark(), 5);
+        assert_eq!(log.abandon(retried).to_string(), "injected");
+        assert_eq!(log.high_water_mark(), 16);
+    }
+
+    #[test]
+    fn reservation_overflow() {
+        let log = Appender::with_offset(Mutex::new(Vec::new()), u64::MAX - 1);
+        let err = log.append(b"ab").unwrap_err();
+        assert_eq!(err.offset(), None);
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        log.abandon(err);
+        assert_eq!(log.reserved(), u64::MAX - 1);
+        assert_eq!(log.high_water_mark(), u64::MAX - 1);
+    }
+}
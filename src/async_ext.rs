@@ -0,0 +1,377 @@
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+
+#[cfg(feature = "blocking")]
+use blocking;
+#[cfg(feature = "blocking")]
+use std::sync::Arc;
+
+#[cfg(feature = "tokio")]
+use std::fs::File;
+#[cfg(feature = "tokio")]
+use std::task::{Context, Poll};
+#[cfg(feature = "tokio")]
+use tokio;
+
+use TransferError;
+#[cfg(any(feature = "blocking", feature = "tokio"))]
+use {ReadAt, WriteAt};
+
+/// The future returned by the methods of `AsyncFileExt`.
+///
+/// It resolves to the result of the operation together with the buffer that
+/// was passed in.
+pub type BufFuture<T> = Pin<Box<dyn Future<Output = (T, Vec<u8>)> + Send>>;
+
+/// This trait provides asynchronous methods for reading and writing files at
+/// specified offsets.
+///
+/// The methods take ownership of the buffer and hand it back once the
+/// operation has completed. This makes them cancellation-safe: if the
+/// returned future is dropped, the operation may still run to completion in
+/// the background, but it never accesses memory owned by the caller.
+///
+/// The returned futures do not borrow `self`, so they can be spawned as
+/// tasks.
+pub trait AsyncFileExt {
+    /// Reads up to `buf.len()` bytes, starting at a given file offset.
+    ///
+    /// Resolves to the number of bytes read, which are located at the start
+    /// of the returned buffer. See `ReadAt::read_offset`.
+    fn read_offset(&self, buf: Vec<u8>, offset: u64) -> BufFuture<io::Result<usize>>;
+
+    /// Writes up to `buf.len()` bytes, starting at a given file offset.
+    ///
+    /// Resolves to the number of bytes written. See `WriteAt::write_offset`.
+    fn write_offset(&self, buf: Vec<u8>, offset: u64) -> BufFuture<io::Result<usize>>;
+
+    /// Reads exactly `buf.len()` bytes, starting at a given file offset.
+    ///
+    /// See `ReadAt::read_exact_offset`.
+    fn read_exact_offset(&self, buf: Vec<u8>, offset: u64) -> BufFuture<Result<(), TransferError>>;
+
+    /// Writes the entire buffer, starting at a given file offset.
+    ///
+    /// See `WriteAt::write_all_offset`.
+    fn write_all_offset(&self, buf: Vec<u8>, offset: u64) -> BufFuture<Result<(), TransferError>>;
+}
+
+/// A wrapper around a `ReadAt` and `WriteAt` implementor, performing its
+/// operations asynchronously on a thread pool.
+///
+/// This works with any async runtime, using the thread pool of the
+/// `blocking` crate. It can be cloned cheaply, all clones sharing the same
+/// underlying file.
+///
+/// Requires the `blocking` feature.
+#[cfg(feature = "blocking")]
+#[derive(Debug)]
+pub struct AsyncFile<T> {
+    inner: Arc<T>,
+}
+
+#[cfg(feature = "blocking")]
+impl<T> AsyncFile<T> {
+    /// Creates a new `AsyncFile` from a file or any other `ReadAt` and
+    /// `WriteAt` implementor.
+    pub fn new(inner: T) -> AsyncFile<T> {
+        AsyncFile::from_arc(Arc::new(inner))
+    }
+
+    /// Creates a new `AsyncFile` sharing an existing `Arc`.
+    pub fn from_arc(inner: Arc<T>) -> AsyncFile<T> {
+        AsyncFile { inner }
+    }
+
+    /// Returns a reference to the underlying file.
+    pub fn get_ref(&self) -> &Arc<T> {
+        &self.inner
+    }
+}
+
+#[cfg(feature = "blocking")]
+impl<T> Clone for AsyncFile<T> {
+    fn clone(&self) -> AsyncFile<T> {
+        AsyncFile::from_arc(self.inner.clone())
+    }
+}
+
+#[cfg(feature = "blocking")]
+impl<T> AsyncFileExt for AsyncFile<T>
+where
+    T: ReadAt + WriteAt + Send + Sync + 'static,
+{
+    fn read_offset(&self, mut buf: Vec<u8>, offset: u64) -> BufFuture<io::Result<usize>> {
+        let inner = self.inner.clone();
+        Box::pin(blocking::unblock(move || {
+            (inner.read_offset(&mut buf, offset), buf)
+        }))
+    }
+
+    fn write_offset(&self, buf: Vec<u8>, offset: u64) -> BufFuture<io::Result<usize>> {
+        let inner = self.inner.clone();
+        Box::pin(blocking::unblock(move || {
+            (inner.write_offset(&buf, offset), buf)
+        }))
+    }
+
+    fn read_exact_offset(
+        &self,
+        mut buf: Vec<u8>,
+        offset: u64,
+    ) -> BufFuture<Result<(), TransferError>> {
+        let inner = self.inner.clone();
+        Box::pin(blocking::unblock(move || {
+            (inner.read_exact_offset(&mut buf, offset), buf)
+        }))
+    }
+
+    fn write_all_offset(&self, buf: Vec<u8>, offset: u64) -> BufFuture<Result<(), TransferError>> {
+        let inner = self.inner.clone();
+        Box::pin(blocking::unblock(move || {
+            (inner.write_all_offset(&buf, offset), buf)
+        }))
+    }
+}
+
+// Runs `f` on tokio's blocking thread pool. If the task panics or is
+// cancelled by a runtime shutdown, the buffer is lost and the error is
+// converted using `on_error`.
+#[cfg(feature = "tokio")]
+fn spawn_blocking<T, F>(f: F, on_error: fn(io::Error) -> T) -> BufFuture<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> (T, Vec<u8>) + Send + 'static,
+{
+    Box::pin(TokioBlocking {
+        handle: tokio::task::spawn_blocking(f),
+        on_error,
+    })
+}
+
+#[cfg(feature = "tokio")]
+struct TokioBlocking<T> {
+    handle: tokio::task::JoinHandle<(T, Vec<u8>)>,
+    on_error: fn(io::Error) -> T,
+}
+
+#[cfg(feature = "tokio")]
+impl<T> Future for TokioBlocking<T> {
+    type Output = (T, Vec<u8>);
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<(T, Vec<u8>)> {
+        match Pin::new(&mut self.handle).poll(cx) {
+            Poll::Ready(Ok(result)) => Poll::Ready(result),
+            Poll::Ready(Err(e)) => Poll::Ready(((self.on_error)(io::Error::other(e)), Vec::new())),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+// Duplicates the file descriptor or handle of a tokio file, so that the
+// blocking task stays valid even if the tokio file is closed in the
+// meantime.
+#[cfg(all(feature = "tokio", unix))]
+fn dup(file: &tokio::fs::File) -> io::Result<File> {
+    use std::os::unix::io::AsFd;
+    file.as_fd().try_clone_to_owned().map(File::from)
+}
+
+#[cfg(all(feature = "tokio", windows))]
+fn dup(file: &tokio::fs::File) -> io::Result<File> {
+    use std::os::windows::io::AsHandle;
+    file.as_handle().try_clone_to_owned().map(File::from)
+}
+
+/// Operations are performed on tokio's blocking thread pool, using a
+/// duplicate of the file's descriptor or handle.
+///
+/// If the blocking task panics, the future resolves to an error and an empty
+/// buffer.
+///
+/// Data written through the `AsyncWrite` implementation of the tokio file is
+/// buffered and written in the background, not through the duplicate. It is
+/// not visible to these methods until it has been flushed, e.g. using
+/// `AsyncWriteExt::flush`.
+///
+/// Requires the `tokio` feature.
+#[cfg(feature = "tokio")]
+impl AsyncFileExt for tokio::fs::File {
+    fn read_offset(&self, mut buf: Vec<u8>, offset: u64) -> BufFuture<io::Result<usize>> {
+        let file = match dup(self) {
+            Ok(file) => file,
+            Err(e) => return Box::pin(::std::future::ready((Err(e), buf))),
+        };
+        spawn_blocking(move || (file.read_offset(&mut buf, offset), buf), Err)
+    }
+
+    fn write_offset(&self, buf: Vec<u8>, offset: u64) -> BufFuture<io::Result<usize>> {
+        let file = match dup(self) {
+            Ok(file) => file,
+            Err(e) => return Box::pin(::std::future::ready((Err(e), buf))),
+        };
+        spawn_blocking(move || (file.write_offset(&buf, offset), buf), Err)
+    }
+
+    fn read_exact_offset(
+        &self,
+        mut buf: Vec<u8>,
+        offset: u64,
+    ) -> BufFuture<Result<(), TransferError>> {
+        let file = match dup(self) {
+            Ok(file) => file,
+            Err(e) => return Box::pin(::std::future::ready((Err(TransferError::new(0, e)), buf))),
+        };
+        spawn_blocking(
+            move || (file.read_exact_offset(&mut buf, offset), buf),
+            |e| Err(TransferError::new(0, e)),
+        )
+    }
+
+    fn write_all_offset(&self, buf: Vec<u8>, offset: u64) -> BufFuture<Result<(), TransferError>> {
+        let file = match dup(self) {
+            Ok(file) => file,
+            Err(e) => return Box::pin(::std::future::ready((Err(TransferError::new(0, e)), buf))),
+        };
+        spawn_blocking(
+            move || (file.write_all_offset(&buf, offset), buf),
+            |e| Err(TransferError::new(0, e)),
+        )
+    }
+}
+
+#[cfg(all(test, any(feature = "blocking", feature = "tokio")))]
+mod tests {
+    #[cfg(feature = "blocking")]
+    use std::fs::File;
+    #[cfg(feature = "blocking")]
+    use std::future::Future;
+    use std::io;
+    #[cfg(feature = "blocking")]
+    use std::sync::{Arc, Mutex};
+    #[cfg(feature = "blocking")]
+    use std::task::{Context, Poll, Wake, Waker};
+    #[cfg(feature = "blocking")]
+    use std::thread::{self, Thread};
+
+    #[cfg(feature = "tokio")]
+    use tokio;
+
+    use super::{AsyncFileExt, BufFuture};
+    #[cfg(feature = "tokio")]
+    use test_util::temp_file;
+    #[cfg(feature = "blocking")]
+    use AsyncFile;
+    use TransferError;
+
+    #[cfg(feature = "blocking")]
+    struct ThreadWaker(Thread);
+
+    #[cfg(feature = "blocking")]
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    // A minimal executor, as the thread pool of `blocking` works with any
+    // runtime.
+    #[cfg(feature = "blocking")]
+    fn block_on<F: Future>(fut: F) -> F::Output {
+        let mut fut = Box::pin(fut);
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            match fut.as_mut().poll(&mut cx) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    // Checks the operations of `file`, which initially holds `b"0123456789"`,
+    // using `run` to drive the futures.
+    fn check_round_trip<F, R>(file: &F, run: R)
+    where
+        F: AsyncFileExt,
+        R: Fn(BufFuture<io::Result<usize>>) -> (io::Result<usize>, Vec<u8>),
+    {
+        let (n, buf) = run(file.write_offset(b"abc".to_vec(), 8));
+        assert_eq!(n.unwrap(), 3);
+        assert_eq!(buf, b"abc");
+        let (n, buf) = run(file.read_offset(vec![0; 16], 6));
+        assert_eq!(n.unwrap(), 5);
+        assert_eq!(&buf[..5], b"67abc");
+        assert_eq!(buf.len(), 16);
+    }
+
+    // Checks that errors of `file`, which holds `b"01234567abc"`, are
+    // passed on, using `run` to drive the futures.
+    fn check_transfer_errors<F, R>(file: &F, run: R)
+    where
+        F: AsyncFileExt,
+        R: Fn(BufFuture<Result<(), TransferError>>) -> (Result<(), TransferError>, Vec<u8>),
+    {
+        let (result, buf) = run(file.write_all_offset(b"xy".to_vec(), 1));
+        result.unwrap();
+        assert_eq!(buf, b"xy");
+        let (result, buf) = run(file.read_exact_offset(vec![0; 4], 0));
+        result.unwrap();
+        assert_eq!(buf, b"0xy3");
+        let (result, buf) = run(file.read_exact_offset(vec![0; 4], 9));
+        let err = result.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(err.transferred(), 2);
+        assert_eq!(&buf[..2], b"bc");
+    }
+
+    #[cfg(feature = "blocking")]
+    #[test]
+    fn blocking_round_trip() {
+        let file = AsyncFile::new(Mutex::new(b"0123456789".to_vec()));
+        check_round_trip(&file.clone(), block_on);
+        check_transfer_errors(&file, block_on);
+        assert_eq!(*file.get_ref().lock().unwrap(), b"0xy34567abc");
+    }
+
+    #[cfg(feature = "blocking")]
+    #[test]
+    fn blocking_errors() {
+        let file = AsyncFile::new(File::open("Cargo.toml").unwrap());
+        let (n, buf) = block_on(file.write_offset(b"abc".to_vec(), 0));
+        assert!(n.is_err());
+        assert_eq!(buf, b"abc");
+        let (result, _) = block_on(file.write_all_offset(b"abc".to_vec(), 0));
+        assert_eq!(result.unwrap_err().transferred(), 0);
+    }
+
+    #[cfg(feature = "tokio")]
+    fn runtime() -> tokio::runtime::Runtime {
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn tokio_round_trip() {
+        let rt = runtime();
+        let _guard = rt.enter();
+        let file = tokio::fs::File::from_std(temp_file(b"0123456789"));
+        check_round_trip(&file, |f| rt.block_on(f));
+        check_transfer_errors(&file, |f| rt.block_on(f));
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn tokio_errors() {
+        let rt = runtime();
+        let _guard = rt.enter();
+        let file = tokio::fs::File::from_std(::std::fs::File::open("Cargo.toml").unwrap());
+        let (n, buf) = rt.block_on(file.write_offset(b"abc".to_vec(), 0));
+        assert!(n.is_err());
+        assert_eq!(buf, b"abc");
+        let (result, _) = rt.block_on(file.write_all_offset(b"abc".to_vec(), 0));
+        assert_eq!(result.unwrap_err().transferred(), 0);
+    }
+}
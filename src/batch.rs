@@ -0,0 +1,423 @@
+use std::cmp;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use space;
+use {lock_ignoring_poison, ReadAt, TransferError, WriteAt};
+
+/// Batches with fewer entries than this are processed sequentially, as the
+/// cost of spawning threads would outweigh the gain.
+const PARALLEL_THRESHOLD: usize = 16;
+
+/// The minimum number of entries processed by each worker thread.
+const MIN_PER_THREAD: usize = 4;
+
+/// The minimum number of bytes transferred by each worker thread. Small
+/// transfers mostly hit the page cache and complete faster than a thread can
+/// be spawned.
+const MIN_BYTES_PER_THREAD: usize = 256 * 1024;
+
+/// Returns the number of entries each worker should process for a batch of
+/// `len` entries transferring `bytes` bytes in total, or `None` if the batch
+/// should be processed sequentially.
+fn chunk_size(len: usize, bytes: usize) -> Option<usize> {
+    if len < PARALLEL_THRESHOLD {
+        return None;
+    }
+    let threads = thread::available_parallelism().map_or(1, |n| n.get());
+    let threads = cmp::min(threads, len / MIN_PER_THREAD);
+    let threads = cmp::min(threads, bytes / MIN_BYTES_PER_THREAD);
+    if threads <= 1 {
+        return None;
+    }
+    Some(len.div_ceil(threads))
+}
+
+// Runs `f` on each chunk in its own scoped thread, concatenating the results
+// in order.
+fn run_chunks<C, I, F>(chunks: I, f: F) -> Vec<io::Result<usize>>
+where
+    C: Send,
+    I: Iterator<Item = C>,
+    F: Fn(C) -> Vec<io::Result<usize>> + Sync,
+{
+    let f = &f;
+    thread::scope(|s| {
+        let handles: Vec<_> = chunks.map(|c| s.spawn(move || f(c))).collect();
+        handles
+            .into_iter()
+            .flat_map(|h| h.join().unwrap_or_else(|e| ::std::panic::resume_unwind(e)))
+            .collect()
+    })
+}
+
+/// Performs the reads in `reqs` using `read`, spread across worker threads if
+/// the batch has enough entries and bytes.
+pub fn read_parallel<F>(reqs: &mut [(u64, &mut [u8])], read: F) -> Vec<io::Result<usize>>
+where
+    F: Fn(&mut [u8], u64) -> io::Result<usize> + Sync,
+{
+    let read_all = |chunk: &mut [(u64, &mut [u8])]| {
+        chunk
+            .iter_mut()
+            .map(|&mut (offset, ref mut buf)| read(buf, offset))
+            .collect()
+    };
+    let bytes = reqs.iter().fold(0usize, |n, r| n.saturating_add(r.1.len()));
+    match chunk_size(reqs.len(), bytes) {
+        Some(size) => run_chunks(reqs.chunks_mut(size), read_all),
+        None => read_all(reqs),
+    }
+}
+
+/// Performs the writes in `reqs` using `write`, spread across worker threads
+/// if the batch has enough entries and bytes.
+pub fn write_parallel<F>(reqs: &[(u64, &[u8])], write: F) -> Vec<io::Result<usize>>
+where
+    F: Fn(&[u8], u64) -> io::Result<usize> + Sync,
+{
+    let write_all = |chunk: &[(u64, &[u8])]| {
+        chunk
+            .iter()
+            .map(|&(offset, buf)| write(buf, offset))
+            .collect()
+    };
+    let bytes = reqs.iter().fold(0usize, |n, r| n.saturating_add(r.1.len()));
+    match chunk_size(reqs.len(), bytes) {
+        Some(size) => run_chunks(reqs.chunks(size), write_all),
+        None => write_all(reqs),
+    }
+}
+
+/// Passes the entries of a batch on to `forward` as a single batch, except
+/// for those that are answered right away.
+///
+/// Each item of `reqs` is either `Ok` with a request to forward, or `Err`
+/// with the result of an entry that is not forwarded. The results returned
+/// by `forward`, one per forwarded request, are merged with the others in
+/// the order of `reqs`.
+pub fn forward_batch<R, I, F>(reqs: I, forward: F) -> Vec<io::Result<usize>>
+where
+    I: IntoIterator<Item = Result<R, io::Result<usize>>>,
+    F: FnOnce(&mut [R]) -> Vec<io::Result<usize>>,
+{
+    let mut results = Vec::new();
+    let mut indices = Vec::new();
+    let mut inner_reqs = Vec::new();
+    for (i, req) in reqs.into_iter().enumerate() {
+        match req {
+            Ok(req) => {
+                results.push(Ok(0));
+                indices.push(i);
+                inner_reqs.push(req);
+            }
+            Err(result) => results.push(result),
+        }
+    }
+    let inner_results = forward(&mut inner_reqs);
+    for (i, result) in indices.into_iter().zip(inner_results) {
+        results[i] = result;
+    }
+    results
+}
+
+/// Reads `buf.len()` bytes starting at `offset` in chunks of `chunk_size`
+/// bytes, using up to `threads` threads concurrently.
+///
+/// Each chunk is read using `ReadAt::read_exact_offset` on the shared
+/// `file`. If `threads` is zero, the available parallelism of the system is
+/// used. The calling thread takes part in the work.
+///
+/// If reading any chunk fails, no further chunks are started, and the error
+/// of the failing chunk with the lowest offset is returned. Its
+/// `TransferError::transferred` counts the bytes from the start of `buf` up
+/// to where that chunk stopped, all of which have been read successfully.
+/// The contents of the rest of `buf` are unspecified.
+///
+/// Fails with `ErrorKind::InvalidInput` without reading anything if the
+/// range extends past `u64::MAX`.
+///
+/// # Panics
+///
+/// Panics if `chunk_size` is zero.
+pub fn read_range_parallel<T>(
+    file: &T,
+    offset: u64,
+    buf: &mut [u8],
+    chunk_size: usize,
+    threads: usize,
+) -> Result<(), TransferError>
+where
+    T: ReadAt + Sync + ?Sized,
+{
+    assert!(chunk_size != 0, "chunk size must be non-zero");
+    space::checked_end(offset, buf.len() as u64).map_err(|e| TransferError::new(0, e))?;
+    let chunks = buf.len().div_ceil(chunk_size);
+    run_range(
+        buf.chunks_mut(chunk_size),
+        chunks,
+        chunk_size,
+        threads,
+        |chunk, i| file.read_exact_offset(chunk, chunk_offset(offset, i, chunk_size)?),
+    )
+}
+
+/// Writes `buf` starting at `offset` in chunks of `chunk_size` bytes, using
+/// up to `threads` threads concurrently.
+///
+/// Each chunk is written using `WriteAt::write_all_offset` on the shared
+/// `file`. If `threads` is zero, the available parallelism of the system is
+/// used. The calling thread takes part in the work.
+///
+/// If writing any chunk fails, no further chunks are started, and the error
+/// of the failing chunk with the lowest offset is returned. Its
+/// `TransferError::transferred` counts the bytes from the start of `buf` up
+/// to where that chunk stopped, all of which have been written successfully.
+/// Chunks after the failing one may or may not have been written.
+///
+/// Fails with `ErrorKind::InvalidInput` without writing anything if the
+/// range extends past `u64::MAX`.
+///
+/// # Panics
+///
+/// Panics if `chunk_size` is zero.
+pub fn write_range_parallel<T>(
+    file: &T,
+    offset: u64,
+    buf: &[u8],
+    chunk_size: usize,
+    threads: usize,
+) -> Result<(), TransferError>
+where
+    T: WriteAt + Sync + ?Sized,
+{
+    assert!(chunk_size != 0, "chunk size must be non-zero");
+    space::checked_end(offset, buf.len() as u64).map_err(|e| TransferError::new(0, e))?;
+    let chunks = buf.len().div_ceil(chunk_size);
+    run_range(
+        buf.chunks(chunk_size),
+        chunks,
+        chunk_size,
+        threads,
+        |chunk, i| file.write_all_offset(chunk, chunk_offset(offset, i, chunk_size)?),
+    )
+}
+
+// Returns the offset of the chunk with index `i`.
+fn chunk_offset(offset: u64, i: usize, chunk_size: usize) -> Result<u64, TransferError> {
+    (i as u64)
+        .checked_mul(chunk_size as u64)
+        .and_then(|o| o.checked_add(offset))
+        .ok_or_else(|| {
+            let e = io::Error::new(io::ErrorKind::InvalidInput, "chunk offset overflows u64");
+            TransferError::new(0, e)
+        })
+}
+
+// Hands out the chunks in order to the worker threads. Once a chunk has
+// failed, no further chunks are handed out, so every chunk before the
+// failing one with the lowest index has been processed, making the returned
+// error deterministic.
+fn run_range<C, I, F>(
+    chunks: I,
+    count: usize,
+    chunk_size: usize,
+    threads: usize,
+    f: F,
+) -> Result<(), TransferError>
+where
+    C: Send,
+    I: Iterator<Item = C> + Send,
+    F: Fn(C, usize) -> Result<(), TransferError> + Sync,
+{
+    let threads = if threads == 0 {
+        thread::available_parallelism().map_or(1, |n| n.get())
+    } else {
+        threads
+    };
+    let threads = cmp::min(threads, count);
+    let chunks = Mutex::new(chunks.enumerate());
+    let failed = AtomicBool::new(false);
+    let error: Mutex<Option<(usize, TransferError)>> = Mutex::new(None);
+    let worker = || loop {
+        if failed.load(Ordering::Relaxed) {
+            return;
+        }
+        let (i, chunk) = match lock_ignoring_poison(&chunks).next() {
+            Some(next) => next,
+            None => return,
+        };
+        if let Err(e) = f(chunk, i) {
+            failed.store(true, Ordering::Relaxed);
+            let mut error = lock_ignoring_poison(&error);
+            if error.as_ref().is_none_or(|&(j, _)| i < j) {
+                *error = Some((i, e));
+            }
+        }
+    };
+    thread::scope(|s| {
+        for _ in 1..threads {
+            s.spawn(worker);
+        }
+        worker();
+    });
+    match error.into_inner().unwrap_or_else(|e| e.into_inner()) {
+        None => Ok(()),
+        Some((i, e)) => {
+            let transferred = i * chunk_size + e.transferred();
+            Err(TransferError::new(transferred, e.into_error()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp;
+    use std::io;
+    use std::sync::Mutex;
+    use std::thread;
+    use std::time::Duration;
+
+    use super::{
+        chunk_size, read_parallel, read_range_parallel, write_parallel, write_range_parallel,
+        MIN_BYTES_PER_THREAD,
+    };
+    use {ReadAt, WriteAt};
+
+    const ENTRIES: usize = 64;
+    const ENTRY_SIZE: usize = 4 * MIN_BYTES_PER_THREAD / ENTRIES;
+
+    // Fails entries at multiples of 7, and transfers one byte less than
+    // requested at odd ones, so that each result identifies its entry.
+    fn result(offset: u64, len: usize) -> io::Result<usize> {
+        match offset % 7 {
+            0 => Err(io::Error::other(offset.to_string())),
+            _ => Ok(len - (offset % 2) as usize),
+        }
+    }
+
+    fn check(results: Vec<io::Result<usize>>) {
+        assert_eq!(results.len(), ENTRIES);
+        for (i, r) in results.into_iter().enumerate() {
+            match r {
+                Ok(n) => assert_eq!(n, ENTRY_SIZE - i % 2),
+                Err(e) => assert_eq!(e.to_string(), i.to_string()),
+            }
+        }
+    }
+
+    #[test]
+    fn small_batches_are_sequential() {
+        assert_eq!(chunk_size(ENTRIES, ENTRIES * 4096), None);
+        assert_eq!(chunk_size(4, 1 << 30), None);
+    }
+
+    #[test]
+    fn read_results_in_order() {
+        let mut bufs = vec![vec![0; ENTRY_SIZE]; ENTRIES];
+        let mut reqs: Vec<(u64, &mut [u8])> = bufs
+            .iter_mut()
+            .enumerate()
+            .map(|(i, b)| (i as u64, &mut b[..]))
+            .collect();
+        check(read_parallel(&mut reqs, |buf, offset| {
+            buf[0] = offset as u8;
+            result(offset, buf.len())
+        }));
+        for (i, b) in bufs.iter().enumerate() {
+            assert_eq!(b[0], i as u8);
+        }
+    }
+
+    #[test]
+    fn write_results_in_order() {
+        let bufs = vec![vec![0; ENTRY_SIZE]; ENTRIES];
+        let reqs: Vec<(u64, &[u8])> = bufs
+            .iter()
+            .enumerate()
+            .map(|(i, b)| (i as u64, &b[..]))
+            .collect();
+        check(write_parallel(&reqs, |buf, offset| {
+            result(offset, buf.len())
+        }));
+    }
+
+    #[test]
+    fn range_overflow() {
+        let data = Mutex::new(vec![0; 16]);
+        let mut buf = [0; 8];
+        let e = read_range_parallel(&data, u64::MAX - 4, &mut buf, 2, 2).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(e.transferred(), 0);
+        let e = write_range_parallel(&data, u64::MAX - 4, &buf, 2, 2).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(data.into_inner().unwrap(), [0; 16]);
+    }
+
+    #[test]
+    fn range_round_trip() {
+        let data: Vec<u8> = (0..1000).map(|i| i as u8).collect();
+        let file = Mutex::new(Vec::new());
+        write_range_parallel(&file, 10, &data, 64, 4).unwrap();
+        assert_eq!(file.lock().unwrap()[10..], data[..]);
+        for &threads in &[0, 1, 3, 100] {
+            let mut buf = vec![0; 1000];
+            read_range_parallel(&file, 10, &mut buf, 64, threads).unwrap();
+            assert_eq!(buf, data);
+        }
+    }
+
+    const LIMIT: u64 = 1000;
+    const CHUNK: usize = 64;
+
+    // Transfers data up to `LIMIT` and fails past it, with the offset as the
+    // error message. The chunk containing `LIMIT` and the one after it are
+    // delayed, so that the chunks fail neither in order nor in reverse.
+    struct Limited;
+
+    impl Limited {
+        fn transfer(&self, len: usize, offset: u64) -> io::Result<usize> {
+            let failing = LIMIT / CHUNK as u64 * CHUNK as u64;
+            if offset == failing {
+                thread::sleep(Duration::from_millis(20));
+            } else if offset == failing + CHUNK as u64 {
+                thread::sleep(Duration::from_millis(40));
+            }
+            if offset >= LIMIT {
+                return Err(io::Error::other(offset.to_string()));
+            }
+            Ok(cmp::min(len as u64, LIMIT - offset) as usize)
+        }
+    }
+
+    impl ReadAt for Limited {
+        fn read_offset(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+            self.transfer(buf.len(), offset)
+        }
+        fn size(&self) -> io::Result<u64> {
+            Ok(u64::MAX)
+        }
+    }
+
+    impl WriteAt for Limited {
+        fn write_offset(&self, buf: &[u8], offset: u64) -> io::Result<usize> {
+            self.transfer(buf.len(), offset)
+        }
+        fn set_size(&self, _size: u64) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn range_lowest_error_wins() {
+        let mut buf = vec![0; 2048];
+        let e = read_range_parallel(&Limited, 0, &mut buf, CHUNK, 4).unwrap_err();
+        assert_eq!(e.transferred(), LIMIT as usize);
+        assert_eq!(e.error().to_string(), LIMIT.to_string());
+        let e = write_range_parallel(&Limited, 0, &buf, CHUNK, 4).unwrap_err();
+        assert_eq!(e.transferred(), LIMIT as usize);
+        assert_eq!(e.error().to_string(), LIMIT.to_string());
+    }
+}
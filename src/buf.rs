@@ -0,0 +1,303 @@
+use std::cmp;
+use std::fmt;
+use std::marker::PhantomData;
+use std::mem::MaybeUninit;
+use std::ptr;
+
+/// A buffer that is filled incrementally and may start out uninitialized.
+///
+/// This is a stable counterpart of the standard library's `BorrowedBuf`. It
+/// wraps a byte slice and keeps track of two regions at its start: the part
+/// that has been filled with data and the (possibly larger) part that is
+/// known to be initialized.
+///
+/// ```text
+/// [    filled    |  initialized  |  uninitialized  ]
+/// ```
+///
+/// The buffer is filled through a `ReadBufCursor` obtained from `unfilled`,
+/// which is what `ReadAt::read_buf_offset` takes. Safe code can only ever
+/// observe initialized bytes, so implementors of `read_buf_offset` can read
+/// directly into uninitialized memory without the caller having to zero it
+/// first.
+///
+/// ```
+/// use file_offset::{ReadAt, ReadBuf};
+/// use std::mem::MaybeUninit;
+///
+/// let data: &[u8] = b"hello world";
+/// let mut storage = [MaybeUninit::uninit(); 5];
+/// let mut buf = ReadBuf::uninit(&mut storage);
+/// data.read_buf_offset(buf.unfilled(), 6).unwrap();
+/// assert_eq!(buf.filled(), b"world");
+/// ```
+pub struct ReadBuf<'a> {
+    buf: &'a mut [MaybeUninit<u8>],
+    filled: usize,
+    initialized: usize,
+}
+
+impl<'a> ReadBuf<'a> {
+    /// Creates a new `ReadBuf` from a fully initialized buffer.
+    pub fn new(buf: &'a mut [u8]) -> ReadBuf<'a> {
+        let initialized = buf.len();
+        // `u8` and `MaybeUninit<u8>` have the same layout, and the buffer is
+        // never de-initialized through the returned slice.
+        let buf = unsafe { &mut *(buf as *mut [u8] as *mut [MaybeUninit<u8>]) };
+        ReadBuf {
+            buf,
+            filled: 0,
+            initialized,
+        }
+    }
+
+    /// Creates a new `ReadBuf` from a possibly uninitialized buffer.
+    pub fn uninit(buf: &'a mut [MaybeUninit<u8>]) -> ReadBuf<'a> {
+        ReadBuf {
+            buf,
+            filled: 0,
+            initialized: 0,
+        }
+    }
+
+    /// Returns the total size of the buffer.
+    pub fn capacity(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Returns the number of bytes that can still be filled.
+    pub fn remaining(&self) -> usize {
+        self.buf.len() - self.filled
+    }
+
+    /// Returns the filled part of the buffer.
+    pub fn filled(&self) -> &[u8] {
+        unsafe { &*(&self.buf[..self.filled] as *const [MaybeUninit<u8>] as *const [u8]) }
+    }
+
+    /// Returns the filled part of the buffer mutably.
+    pub fn filled_mut(&mut self) -> &mut [u8] {
+        unsafe { &mut *(&mut self.buf[..self.filled] as *mut [MaybeUninit<u8>] as *mut [u8]) }
+    }
+
+    /// Consumes the `ReadBuf`, returning the filled part of the buffer.
+    pub fn into_filled(self) -> &'a mut [u8] {
+        let filled = self.filled;
+        unsafe { &mut *(&mut self.buf[..filled] as *mut [MaybeUninit<u8>] as *mut [u8]) }
+    }
+
+    /// Returns a cursor for appending to the filled part of the buffer.
+    pub fn unfilled(&mut self) -> ReadBufCursor<'_> {
+        ReadBufCursor {
+            start: self.filled,
+            buf: self.buf,
+            filled: &mut self.filled,
+            initialized: &mut self.initialized,
+            _invariant: PhantomData,
+        }
+    }
+
+    /// Clears the filled part of the buffer, keeping it initialized.
+    pub fn clear(&mut self) {
+        self.filled = 0;
+    }
+}
+
+impl<'a> fmt::Debug for ReadBuf<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ReadBuf")
+            .field("filled", &self.filled)
+            .field("initialized", &self.initialized)
+            .field("capacity", &self.capacity())
+            .finish()
+    }
+}
+
+/// A writeable view of the unfilled part of a `ReadBuf`, created by
+/// `ReadBuf::unfilled`.
+///
+/// This is a stable counterpart of the standard library's `BorrowedCursor`.
+/// Data can only be appended through the cursor, never removed, and the
+/// cursor cannot be redirected to a different buffer. The owner of the
+/// `ReadBuf` can therefore rely on its filled part being initialized after
+/// passing the cursor to untrusted code, such as an implementation of
+/// `ReadAt::read_buf_offset`.
+pub struct ReadBufCursor<'a> {
+    buf: &'a mut [MaybeUninit<u8>],
+    filled: &'a mut usize,
+    initialized: &'a mut usize,
+    // The number of filled bytes when the cursor was created.
+    start: usize,
+    // Makes `'a` invariant, like for `BorrowedCursor`.
+    _invariant: PhantomData<fn(&'a ()) -> &'a ()>,
+}
+
+impl<'a> ReadBufCursor<'a> {
+    /// Returns a shorter-lived cursor for the same buffer, so that it can be
+    /// passed by value while still being used afterwards.
+    pub fn reborrow(&mut self) -> ReadBufCursor<'_> {
+        ReadBufCursor {
+            start: *self.filled,
+            buf: self.buf,
+            filled: self.filled,
+            initialized: self.initialized,
+            _invariant: PhantomData,
+        }
+    }
+
+    /// Returns the number of bytes that can still be filled.
+    pub fn capacity(&self) -> usize {
+        self.buf.len() - *self.filled
+    }
+
+    /// Returns the number of bytes filled through this cursor.
+    pub fn written(&self) -> usize {
+        *self.filled - self.start
+    }
+
+    /// Returns the unfilled part of the buffer, initializing it with zeros
+    /// where necessary.
+    ///
+    /// Each byte is zeroed at most once over the lifetime of the `ReadBuf`.
+    pub fn initialize_unfilled(&mut self) -> &mut [u8] {
+        if *self.initialized < self.buf.len() {
+            let uninit = &mut self.buf[*self.initialized..];
+            unsafe {
+                ptr::write_bytes(uninit.as_mut_ptr(), 0, uninit.len());
+            }
+            *self.initialized = self.buf.len();
+        }
+        unsafe { &mut *(&mut self.buf[*self.filled..] as *mut [MaybeUninit<u8>] as *mut [u8]) }
+    }
+
+    /// Returns the unfilled part of the buffer, which may be uninitialized.
+    ///
+    /// # Safety
+    ///
+    /// The caller must not de-initialize any bytes that have already been
+    /// initialized, e.g. by writing `MaybeUninit::uninit()` to them.
+    pub unsafe fn unfilled_mut(&mut self) -> &mut [MaybeUninit<u8>] {
+        &mut self.buf[*self.filled..]
+    }
+
+    /// Asserts that the first `n` bytes of the unfilled part of the buffer
+    /// are initialized.
+    ///
+    /// This does not mark them as filled; use `advance` afterwards.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that the first `n` unfilled bytes have been
+    /// initialized.
+    pub unsafe fn assume_init(&mut self, n: usize) {
+        *self.initialized = cmp::max(*self.initialized, *self.filled + n);
+    }
+
+    /// Marks the next `n` bytes of the buffer as filled.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the bytes have not been initialized.
+    pub fn advance(&mut self, n: usize) {
+        let filled = self.filled.checked_add(n).expect("filled overflow");
+        assert!(
+            filled <= *self.initialized,
+            "advanced past the initialized part of the buffer"
+        );
+        *self.filled = filled;
+    }
+
+    /// Appends data to the filled part of the buffer.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `data` is larger than the remaining space.
+    pub fn put_slice(&mut self, data: &[u8]) {
+        assert!(
+            data.len() <= self.capacity(),
+            "put_slice exceeds the remaining space"
+        );
+        let start = *self.filled;
+        let end = start + data.len();
+        // `data` cannot overlap with the unfilled part of the buffer, as we
+        // hold a mutable reference to it.
+        unsafe {
+            ptr::copy_nonoverlapping(
+                data.as_ptr(),
+                self.buf[start..end].as_mut_ptr() as *mut u8,
+                data.len(),
+            );
+        }
+        *self.initialized = cmp::max(*self.initialized, end);
+        *self.filled = end;
+    }
+}
+
+impl<'a> fmt::Debug for ReadBufCursor<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ReadBufCursor")
+            .field("written", &self.written())
+            .field("capacity", &self.capacity())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::mem::MaybeUninit;
+
+    use {ReadAt, ReadBuf, ReadBufCursor};
+
+    // Fills the buffer in two steps through reborrowed cursors, reporting a
+    // bogus size from a separate buffer of its own.
+    struct TwoStep;
+
+    impl ReadAt for TwoStep {
+        fn read_offset(&self, _buf: &mut [u8], _offset: u64) -> io::Result<usize> {
+            unreachable!()
+        }
+        fn size(&self) -> io::Result<u64> {
+            Ok(0)
+        }
+        fn read_buf_offset(&self, mut buf: ReadBufCursor, _offset: u64) -> io::Result<()> {
+            buf.reborrow().put_slice(b"a");
+            buf.reborrow().put_slice(b"b");
+            assert_eq!(buf.written(), 2);
+            let mut other = [0; 64];
+            let mut other = ReadBuf::new(&mut other);
+            let mut cursor = other.unfilled();
+            cursor.advance(64);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn cursor_tracks_progress() {
+        let mut storage = [MaybeUninit::uninit(); 8];
+        let mut buf = ReadBuf::uninit(&mut storage);
+        buf.unfilled().put_slice(b"xy");
+        let mut cursor = buf.unfilled();
+        assert_eq!(cursor.capacity(), 6);
+        assert_eq!(cursor.written(), 0);
+        assert_eq!(cursor.initialize_unfilled(), &[0; 6]);
+        cursor.advance(2);
+        assert_eq!(cursor.written(), 2);
+        assert_eq!(buf.filled(), b"xy\0\0");
+    }
+
+    #[test]
+    #[should_panic(expected = "advanced past the initialized part")]
+    fn advance_uninitialized() {
+        let mut storage = [MaybeUninit::uninit(); 8];
+        let mut buf = ReadBuf::uninit(&mut storage);
+        buf.unfilled().advance(1);
+    }
+
+    #[test]
+    fn read_to_vec_only_trusts_own_buffer() {
+        let mut vec = Vec::new();
+        assert_eq!(TwoStep.read_to_vec_offset(&mut vec, 0, 2).unwrap(), 2);
+        assert_eq!(vec, b"ab");
+    }
+}
@@ -0,0 +1,277 @@
+#[cfg(feature = "bytemuck")]
+use bytemuck;
+#[cfg(feature = "bytemuck")]
+use bytemuck::Pod;
+use std::io;
+#[cfg(feature = "bytemuck")]
+use std::slice;
+
+use {ReadAt, TransferError, WriteAt};
+
+macro_rules! read_methods {
+    ($($ty:ident, $le:ident, $be:ident;)*) => {
+        $(
+            #[doc = concat!("Reads a little-endian `", stringify!($ty), "` at the given offset.")]
+            #[inline]
+            fn $le(&self, offset: u64) -> io::Result<$ty> {
+                self.read_array_at(offset).map($ty::from_le_bytes)
+            }
+
+            #[doc = concat!("Reads a big-endian `", stringify!($ty), "` at the given offset.")]
+            #[inline]
+            fn $be(&self, offset: u64) -> io::Result<$ty> {
+                self.read_array_at(offset).map($ty::from_be_bytes)
+            }
+        )*
+    };
+}
+
+macro_rules! write_methods {
+    ($($ty:ident, $le:ident, $be:ident;)*) => {
+        $(
+            #[doc = concat!("Writes a little-endian `", stringify!($ty), "` at the given offset.")]
+            #[inline]
+            fn $le(&self, value: $ty, offset: u64) -> io::Result<()> {
+                self.write_array_at(&value.to_le_bytes(), offset)
+            }
+
+            #[doc = concat!("Writes a big-endian `", stringify!($ty), "` at the given offset.")]
+            #[inline]
+            fn $be(&self, value: $ty, offset: u64) -> io::Result<()> {
+                self.write_array_at(&value.to_be_bytes(), offset)
+            }
+        )*
+    };
+}
+
+// Adds the offset of the value to end-of-file errors.
+fn cvt_eof(err: TransferError, len: usize, offset: u64) -> io::Error {
+    if err.kind() != io::ErrorKind::UnexpectedEof {
+        return err.into();
+    }
+    io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        format!(
+            "unexpected end of data reading {} bytes at offset {}",
+            len, offset
+        ),
+    )
+}
+
+/// Extension methods for reading fixed-size values at specified offsets.
+///
+/// This trait is implemented for all `ReadAt` implementors. All methods read
+/// exactly the size of the value using `ReadAt::read_exact_offset`. If the
+/// data ends before the whole value could be read, an error of kind
+/// `ErrorKind::UnexpectedEof` naming the offset of the value is returned.
+///
+/// ```
+/// use file_offset::ReadAtExt;
+///
+/// let data: &[u8] = &[0x12, 0x34, 0x56, 0x78];
+/// assert_eq!(data.read_u16_be_at(1).unwrap(), 0x3456);
+/// assert_eq!(data.read_u32_le_at(0).unwrap(), 0x78563412);
+/// assert!(data.read_u32_le_at(1).is_err());
+/// ```
+pub trait ReadAtExt: ReadAt {
+    /// Reads an array of `N` bytes at the given offset.
+    fn read_array_at<const N: usize>(&self, offset: u64) -> io::Result<[u8; N]> {
+        let mut buf = [0; N];
+        self.read_exact_offset(&mut buf, offset)
+            .map_err(|e| cvt_eof(e, N, offset))?;
+        Ok(buf)
+    }
+
+    /// Reads a plain-old-data value at the given offset.
+    ///
+    /// The bytes are read directly into the memory of the returned value,
+    /// without an intermediate buffer. Their order is not changed, i.e. the
+    /// value is read in native endianness.
+    ///
+    /// Requires the `bytemuck` feature.
+    #[cfg(feature = "bytemuck")]
+    fn read_pod_at<P: Pod>(&self, offset: u64) -> io::Result<P> {
+        let mut value = P::zeroed();
+        self.read_pod_slice_at(slice::from_mut(&mut value), offset)?;
+        Ok(value)
+    }
+
+    /// Reads a slice of plain-old-data values at the given offset.
+    ///
+    /// The bytes are read directly into the memory of `values`, without an
+    /// intermediate buffer.
+    ///
+    /// Requires the `bytemuck` feature.
+    #[cfg(feature = "bytemuck")]
+    fn read_pod_slice_at<P: Pod>(&self, values: &mut [P], offset: u64) -> io::Result<()> {
+        let bytes: &mut [u8] = bytemuck::cast_slice_mut(values);
+        let len = bytes.len();
+        self.read_exact_offset(bytes, offset)
+            .map_err(|e| cvt_eof(e, len, offset))
+    }
+
+    /// Reads a `u8` at the given offset.
+    #[inline]
+    fn read_u8_at(&self, offset: u64) -> io::Result<u8> {
+        self.read_array_at::<1>(offset).map(|b| b[0])
+    }
+
+    /// Reads an `i8` at the given offset.
+    #[inline]
+    fn read_i8_at(&self, offset: u64) -> io::Result<i8> {
+        self.read_array_at::<1>(offset).map(|b| b[0] as i8)
+    }
+
+    read_methods! {
+        u16, read_u16_le_at, read_u16_be_at;
+        u32, read_u32_le_at, read_u32_be_at;
+        u64, read_u64_le_at, read_u64_be_at;
+        u128, read_u128_le_at, read_u128_be_at;
+        i16, read_i16_le_at, read_i16_be_at;
+        i32, read_i32_le_at, read_i32_be_at;
+        i64, read_i64_le_at, read_i64_be_at;
+        i128, read_i128_le_at, read_i128_be_at;
+        f32, read_f32_le_at, read_f32_be_at;
+        f64, read_f64_le_at, read_f64_be_at;
+    }
+}
+
+impl<T: ReadAt + ?Sized> ReadAtExt for T {}
+
+/// Extension methods for writing fixed-size values at specified offsets.
+///
+/// This trait is implemented for all `WriteAt` implementors. All methods
+/// write the whole value using `WriteAt::write_all_offset`.
+pub trait WriteAtExt: WriteAt {
+    /// Writes an array of `N` bytes at the given offset.
+    fn write_array_at<const N: usize>(&self, array: &[u8; N], offset: u64) -> io::Result<()> {
+        self.write_all_offset(array, offset).map_err(Into::into)
+    }
+
+    /// Writes a plain-old-data value at the given offset.
+    ///
+    /// The bytes are written directly from the memory of `value`, in native
+    /// endianness.
+    ///
+    /// Requires the `bytemuck` feature.
+    #[cfg(feature = "bytemuck")]
+    fn write_pod_at<P: Pod>(&self, value: &P, offset: u64) -> io::Result<()> {
+        self.write_all_offset(bytemuck::bytes_of(value), offset)
+            .map_err(Into::into)
+    }
+
+    /// Writes a slice of plain-old-data values at the given offset.
+    ///
+    /// The bytes are written directly from the memory of `values`.
+    ///
+    /// Requires the `bytemuck` feature.
+    #[cfg(feature = "bytemuck")]
+    fn write_pod_slice_at<P: Pod>(&self, values: &[P], offset: u64) -> io::Result<()> {
+        self.write_all_offset(bytemuck::cast_slice(values), offset)
+            .map_err(Into::into)
+    }
+
+    /// Writes a `u8` at the given offset.
+    #[inline]
+    fn write_u8_at(&self, value: u8, offset: u64) -> io::Result<()> {
+        self.write_array_at(&[value], offset)
+    }
+
+    /// Writes an `i8` at the given offset.
+    #[inline]
+    fn write_i8_at(&self, value: i8, offset: u64) -> io::Result<()> {
+        self.write_array_at(&[value as u8], offset)
+    }
+
+    write_methods! {
+        u16, write_u16_le_at, write_u16_be_at;
+        u32, write_u32_le_at, write_u32_be_at;
+        u64, write_u64_le_at, write_u64_be_at;
+        u128, write_u128_le_at, write_u128_be_at;
+        i16, write_i16_le_at, write_i16_be_at;
+        i32, write_i32_le_at, write_i32_be_at;
+        i64, write_i64_le_at, write_i64_be_at;
+        i128, write_i128_le_at, write_i128_be_at;
+        f32, write_f32_le_at, write_f32_be_at;
+        f64, write_f64_le_at, write_f64_be_at;
+    }
+}
+
+impl<T: WriteAt + ?Sized> WriteAtExt for T {}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::sync::Mutex;
+
+    use super::{ReadAtExt, WriteAtExt};
+
+    #[test]
+    fn round_trip_le_be() {
+        let data = Mutex::new(Vec::new());
+        data.write_u8_at(0xfe, 0).unwrap();
+        data.write_i8_at(-2, 1).unwrap();
+        data.write_u16_le_at(0x1234, 2).unwrap();
+        data.write_u16_be_at(0x1234, 4).unwrap();
+        data.write_u32_le_at(0x1234_5678, 6).unwrap();
+        data.write_i64_be_at(-0x0102_0304_0506_0708, 10).unwrap();
+        data.write_u128_le_at(u128::MAX - 1, 18).unwrap();
+        data.write_f64_be_at(-1.5, 34).unwrap();
+        data.write_f32_le_at(0.25, 42).unwrap();
+        assert_eq!(
+            &data.lock().unwrap()[..10],
+            b"\xfe\xfe\x34\x12\x12\x34\x78\x56\x34\x12"
+        );
+
+        assert_eq!(data.read_u8_at(0).unwrap(), 0xfe);
+        assert_eq!(data.read_i8_at(1).unwrap(), -2);
+        assert_eq!(data.read_u16_le_at(2).unwrap(), 0x1234);
+        assert_eq!(data.read_u16_be_at(4).unwrap(), 0x1234);
+        assert_eq!(data.read_u16_be_at(2).unwrap(), 0x3412);
+        assert_eq!(data.read_u32_le_at(6).unwrap(), 0x1234_5678);
+        assert_eq!(data.read_i64_be_at(10).unwrap(), -0x0102_0304_0506_0708);
+        assert_eq!(data.read_u128_le_at(18).unwrap(), u128::MAX - 1);
+        assert_eq!(data.read_f64_be_at(34).unwrap(), -1.5);
+        assert_eq!(data.read_f32_le_at(42).unwrap(), 0.25);
+        assert_eq!(data.read_array_at::<3>(3).unwrap(), [0x12, 0x12, 0x34]);
+    }
+
+    #[test]
+    fn eof_error_names_offset() {
+        let data: &[u8] = &[1, 2, 3, 4, 5];
+        let err = data.read_u32_le_at(3).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(
+            err.to_string(),
+            "unexpected end of data reading 4 bytes at offset 3"
+        );
+        let err = data.read_u8_at(u64::MAX).unwrap_err();
+        assert!(err
+            .to_string()
+            .ends_with(&format!("at offset {}", u64::MAX)));
+    }
+
+    #[cfg(feature = "bytemuck")]
+    #[test]
+    fn pod_round_trip() {
+        let data = Mutex::new(Vec::new());
+        data.write_pod_at(&0x0102_0304u32, 1).unwrap();
+        data.write_pod_slice_at(&[[1u16, 2], [3, 4]], 5).unwrap();
+        assert_eq!(
+            &data.lock().unwrap()[1..5],
+            &0x0102_0304u32.to_ne_bytes()[..]
+        );
+
+        assert_eq!(data.read_pod_at::<u32>(1).unwrap(), 0x0102_0304);
+        let mut values = [[0u16; 2]; 2];
+        data.read_pod_slice_at(&mut values, 5).unwrap();
+        assert_eq!(values, [[1, 2], [3, 4]]);
+
+        let err = data.read_pod_slice_at(&mut values, 7).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(
+            err.to_string(),
+            "unexpected end of data reading 8 bytes at offset 7"
+        );
+    }
+}
@@ -0,0 +1,206 @@
+use std::cmp;
+use std::fs::File;
+use std::io;
+
+use sys;
+use {ReadAt, TransferError, WriteAt, BUFFER_SIZE};
+
+// The maximum number of bytes copied by a single `copy_file_range` call.
+const MAX_KERNEL_COPY: u64 = 1 << 30;
+
+/// Copies up to `len` bytes from `src` at `src_offset` to `dst` at
+/// `dst_offset`.
+///
+/// Returns the number of bytes copied, which is less than `len` only if the
+/// end of `src` was reached. Short copies are continued until `len` bytes
+/// have been copied, and interrupted calls are retried. Neither file's
+/// cursor is used.
+///
+/// Fails with `ErrorKind::InvalidInput` without copying anything if `src`
+/// and `dst` refer to the same file and the ranges overlap, as the copy would
+/// overwrite data before reading it.
+///
+/// On error, the returned `TransferError` records how many bytes at the
+/// start of both ranges were copied successfully, saturating at
+/// `usize::MAX`.
+///
+/// # Platform-specific behavior
+///
+/// On Linux and Android, this uses the `copy_file_range` function, so the
+/// data does not pass through userspace, and file systems may share the
+/// underlying blocks (reflinks) or perform the copy on the server. If the
+/// kernel or file system does not support it for the given files, it falls
+/// back to copying through a buffer with `read_offset` and
+/// `write_all_offset`, which is always used on other platforms.
+///
+/// On Windows, overlapping ranges within the same file are not detected,
+/// and the result of copying them is unspecified.
+pub fn copy_range(
+    src: &File,
+    mut src_offset: u64,
+    dst: &File,
+    mut dst_offset: u64,
+    len: u64,
+) -> Result<u64, TransferError> {
+    let src_metadata = src.metadata().map_err(|e| TransferError::new(0, e))?;
+    let dst_metadata = dst.metadata().map_err(|e| TransferError::new(0, e))?;
+    let same_file =
+        sys::file_id(&src_metadata).is_some_and(|id| sys::file_id(&dst_metadata) == Some(id));
+    if same_file && overlaps(src_offset, dst_offset, len) {
+        return Err(TransferError::new(
+            0,
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "source and destination ranges overlap",
+            ),
+        ));
+    }
+    let mut copied = 0;
+    // The kernel rejects special files with the same error as invalid
+    // arguments, so they are copied through a buffer right away.
+    let mut kernel = src_metadata.is_file() && dst_metadata.is_file();
+    let mut buf = Vec::new();
+    while copied < len {
+        let remaining = len - copied;
+        let result = if kernel {
+            let chunk = cmp::min(remaining, MAX_KERNEL_COPY) as usize;
+            match sys::copy_file_range(src, src_offset, dst, dst_offset, chunk) {
+                // Some special files report zero bytes even though they
+                // contain data, so check using a regular read.
+                Ok(0) if copied == 0 => {
+                    kernel = false;
+                    continue;
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::Unsupported => {
+                    kernel = false;
+                    continue;
+                }
+                result => result.map_err(|e| TransferError::new(0, e)),
+            }
+        } else {
+            if buf.is_empty() {
+                buf = vec![0; cmp::min(remaining, BUFFER_SIZE as u64) as usize];
+            }
+            let chunk = cmp::min(remaining, buf.len() as u64) as usize;
+            copy_buffered(src, src_offset, dst, dst_offset, &mut buf[..chunk])
+        };
+        match result {
+            Ok(0) => break,
+            Ok(n) => {
+                copied += n as u64;
+                src_offset += n as u64;
+                dst_offset += n as u64;
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted && e.transferred() == 0 => {}
+            Err(e) => {
+                let transferred = copied + e.transferred() as u64;
+                let transferred = cmp::min(transferred, usize::MAX as u64) as usize;
+                return Err(TransferError::new(transferred, e.into_error()));
+            }
+        }
+    }
+    Ok(copied)
+}
+
+// Returns whether the ranges of `len` bytes at `a` and `b` overlap.
+fn overlaps(a: u64, b: u64, len: u64) -> bool {
+    len != 0 && a < b.saturating_add(len) && b < a.saturating_add(len)
+}
+
+// Copies a single buffer's worth of data. On error, the bytes that were
+// written before the failure are reported as transferred.
+fn copy_buffered(
+    src: &File,
+    src_offset: u64,
+    dst: &File,
+    dst_offset: u64,
+    buf: &mut [u8],
+) -> Result<usize, TransferError> {
+    let n = src
+        .read_offset(buf, src_offset)
+        .map_err(|e| TransferError::new(0, e))?;
+    dst.write_all_offset(&buf[..n], dst_offset)?;
+    Ok(n)
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(unix)]
+    use std::fs::File;
+    use std::io;
+
+    use super::copy_range;
+    use test_util::temp_file;
+    use ReadAt;
+
+    const LEN: usize = 300_000;
+
+    fn pattern() -> Vec<u8> {
+        (0..LEN).map(|i| (i % 251) as u8).collect()
+    }
+
+    fn contents<T: ReadAt>(file: &T) -> Vec<u8> {
+        let mut buf = vec![0; file.size().unwrap() as usize];
+        file.read_exact_offset(&mut buf, 0).unwrap();
+        buf
+    }
+
+    #[test]
+    fn full_copy() {
+        let data = pattern();
+        let src = temp_file(&data);
+        let dst = temp_file(b"");
+        assert_eq!(
+            copy_range(&src, 0, &dst, 10, LEN as u64).unwrap(),
+            LEN as u64
+        );
+        let copied = contents(&dst);
+        assert_eq!(copied[..10], [0; 10]);
+        assert_eq!(copied[10..], data[..]);
+    }
+
+    #[test]
+    fn short_copy_at_eof() {
+        let data = pattern();
+        let src = temp_file(&data);
+        let dst = temp_file(b"");
+        assert_eq!(copy_range(&src, 250_000, &dst, 0, 100_000).unwrap(), 50_000);
+        assert_eq!(contents(&dst), data[250_000..]);
+        assert_eq!(copy_range(&src, LEN as u64, &dst, 0, 10).unwrap(), 0);
+    }
+
+    // Character devices are copied through a buffer.
+    #[cfg(unix)]
+    #[test]
+    fn buffered_fallback() {
+        let src = File::open("/dev/zero").unwrap();
+        let dst = temp_file(&pattern());
+        assert_eq!(copy_range(&src, 0, &dst, 1, 100_000).unwrap(), 100_000);
+        let copied = contents(&dst);
+        assert_eq!(copied[0], 0);
+        assert!(copied[1..100_001].iter().all(|&b| b == 0));
+        assert_eq!(copied[100_001..], pattern()[100_001..]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn same_file() {
+        let data = pattern();
+        let file = temp_file(&data);
+        let clone = file.try_clone().unwrap();
+        for &(src, dst, len) in &[(0, 100, 150_000), (100, 0, 150_000), (5, 5, 1)] {
+            let err = copy_range(&file, src, &clone, dst, len).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+            assert_eq!(err.transferred(), 0);
+        }
+        assert_eq!(contents(&file), data);
+        assert_eq!(
+            copy_range(&file, 0, &file, 150_000, 150_000).unwrap(),
+            150_000
+        );
+        assert_eq!(copy_range(&file, 5, &file, 5, 0).unwrap(), 0);
+        let copied = contents(&file);
+        assert_eq!(copied[..150_000], data[..150_000]);
+        assert_eq!(copied[150_000..], data[..150_000]);
+    }
+}
@@ -0,0 +1,160 @@
+use std::io;
+use std::io::{IoSlice, IoSliceMut, Read, Seek, SeekFrom, Write};
+
+use {ReadAt, WriteAt};
+
+/// A cursor with its own position over a shared reference to a `ReadAt`
+/// and/or `WriteAt` implementor.
+///
+/// `OffsetCursor` implements `Read`, `Write` and `Seek` purely in terms of
+/// `read_offset` and `write_offset`, keeping track of the position itself.
+/// As it never touches the operating system's file cursor, any number of
+/// cursors can be used concurrently on the same file, e.g. from multiple
+/// threads. It can be wrapped in a `BufReader` or `BufWriter` like any other
+/// reader or writer.
+///
+/// Note that on Windows, positioned I/O on a `File` moves its file cursor,
+/// so other users relying on that cursor are still affected.
+///
+/// ```
+/// use file_offset::OffsetCursor;
+/// use std::fs::File;
+/// use std::io::{BufRead, BufReader};
+///
+/// let f = File::open("src/lib.rs").unwrap();
+/// let first = BufReader::new(OffsetCursor::new(&f));
+/// let second = BufReader::new(OffsetCursor::new(&f));
+/// for (a, b) in first.lines().zip(second.lines()) {
+///     assert_eq!(a.unwrap(), b.unwrap());
+/// }
+/// ```
+#[derive(Debug)]
+pub struct OffsetCursor<'a, T: ?Sized + 'a> {
+    inner: &'a T,
+    pos: u64,
+}
+
+impl<'a, T: ?Sized + 'a> OffsetCursor<'a, T> {
+    /// Creates a new cursor over `inner`, positioned at offset zero.
+    pub fn new(inner: &'a T) -> OffsetCursor<'a, T> {
+        OffsetCursor::at(inner, 0)
+    }
+
+    /// Creates a new cursor over `inner`, positioned at the given offset.
+    pub fn at(inner: &'a T, pos: u64) -> OffsetCursor<'a, T> {
+        OffsetCursor { inner, pos }
+    }
+
+    /// Returns the current position of this cursor.
+    pub fn position(&self) -> u64 {
+        self.pos
+    }
+
+    /// Sets the position of this cursor.
+    pub fn set_position(&mut self, pos: u64) {
+        self.pos = pos;
+    }
+
+    /// Returns the underlying reference.
+    pub fn get_ref(&self) -> &'a T {
+        self.inner
+    }
+}
+
+// `Copy` is deliberately not implemented, as copies made implicitly would
+// silently continue from a stale position.
+impl<'a, T: ?Sized + 'a> Clone for OffsetCursor<'a, T> {
+    fn clone(&self) -> OffsetCursor<'a, T> {
+        OffsetCursor {
+            inner: self.inner,
+            pos: self.pos,
+        }
+    }
+}
+
+impl<'a, T: ReadAt + ?Sized + 'a> Read for OffsetCursor<'a, T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read_offset(buf, self.pos)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+
+    fn read_vectored(&mut self, bufs: &mut [IoSliceMut]) -> io::Result<usize> {
+        let n = self.inner.read_vectored_offset(bufs, self.pos)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        let result = self.inner.read_exact_offset(buf, self.pos);
+        match result {
+            Ok(()) => {
+                self.pos += buf.len() as u64;
+                Ok(())
+            }
+            Err(e) => {
+                self.pos += e.transferred() as u64;
+                Err(e.into())
+            }
+        }
+    }
+}
+
+impl<'a, T: WriteAt + ?Sized + 'a> Write for OffsetCursor<'a, T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write_offset(buf, self.pos)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice]) -> io::Result<usize> {
+        let n = self.inner.write_vectored_offset(bufs, self.pos)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        let result = self.inner.write_all_offset(buf, self.pos);
+        match result {
+            Ok(()) => {
+                self.pos += buf.len() as u64;
+                Ok(())
+            }
+            Err(e) => {
+                self.pos += e.transferred() as u64;
+                Err(e.into())
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a, T: ReadAt + ?Sized + 'a> Seek for OffsetCursor<'a, T> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (base, offset) = match pos {
+            SeekFrom::Start(n) => {
+                self.pos = n;
+                return Ok(n);
+            }
+            SeekFrom::End(n) => (self.inner.size()?, n),
+            SeekFrom::Current(n) => (self.pos, n),
+        };
+        match base.checked_add_signed(offset) {
+            Some(n) => {
+                self.pos = n;
+                Ok(n)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )),
+        }
+    }
+
+    fn stream_position(&mut self) -> io::Result<u64> {
+        Ok(self.pos)
+    }
+}
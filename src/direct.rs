@@ -0,0 +1,503 @@
+use std::alloc::{self, Layout};
+use std::cmp;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{IoSlice, IoSliceMut};
+use std::ops::{Deref, DerefMut};
+use std::path::Path;
+use std::ptr::NonNull;
+use std::slice;
+
+use batch;
+use sys;
+use {Advice, ReadAt, RwFlags, TransferError, WriteAt, BUFFER_SIZE};
+
+/// A heap-allocated, zero-initialized byte buffer whose address is aligned
+/// to a given power of two.
+///
+/// Buffers passed to the methods of `DirectFile` must be aligned to
+/// `Alignment::memory`, which a `Vec<u8>` does not guarantee.
+pub struct AlignedBuf {
+    ptr: NonNull<u8>,
+    len: usize,
+    align: usize,
+}
+
+// `AlignedBuf` owns its memory like a `Box<[u8]>`.
+unsafe impl Send for AlignedBuf {}
+unsafe impl Sync for AlignedBuf {}
+
+impl AlignedBuf {
+    /// Allocates a buffer of `len` zero bytes, aligned to `align`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `align` is not a power of two, or if `len` rounded up to a
+    /// multiple of `align` exceeds `isize::MAX`.
+    pub fn new(len: usize, align: usize) -> AlignedBuf {
+        let layout = Layout::from_size_align(len, align).expect("invalid buffer layout");
+        let ptr = if len == 0 {
+            // A dangling but aligned pointer, like `NonNull::dangling`.
+            unsafe { NonNull::new_unchecked(align as *mut u8) }
+        } else {
+            match NonNull::new(unsafe { alloc::alloc_zeroed(layout) }) {
+                Some(ptr) => ptr,
+                None => alloc::handle_alloc_error(layout),
+            }
+        };
+        AlignedBuf { ptr, len, align }
+    }
+
+    /// Returns the length of the buffer.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if the buffer has a length of zero.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the alignment of the buffer's address.
+    pub fn align(&self) -> usize {
+        self.align
+    }
+}
+
+impl Drop for AlignedBuf {
+    fn drop(&mut self) {
+        if self.len != 0 {
+            unsafe {
+                let layout = Layout::from_size_align_unchecked(self.len, self.align);
+                alloc::dealloc(self.ptr.as_ptr(), layout);
+            }
+        }
+    }
+}
+
+impl Deref for AlignedBuf {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl DerefMut for AlignedBuf {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl AsRef<[u8]> for AlignedBuf {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl AsMut<[u8]> for AlignedBuf {
+    fn as_mut(&mut self) -> &mut [u8] {
+        self
+    }
+}
+
+impl Clone for AlignedBuf {
+    fn clone(&self) -> AlignedBuf {
+        let mut buf = AlignedBuf::new(self.len, self.align);
+        buf.copy_from_slice(self);
+        buf
+    }
+}
+
+impl fmt::Debug for AlignedBuf {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AlignedBuf")
+            .field("len", &self.len)
+            .field("align", &self.align)
+            .finish()
+    }
+}
+
+/// The alignment required for direct I/O on a file, as returned by
+/// `direct_io_alignment`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Alignment {
+    /// The alignment of the addresses of buffers.
+    pub memory: usize,
+    /// The alignment of file offsets and of the lengths of buffers.
+    pub offset: usize,
+}
+
+/// Determines the alignment required for direct I/O on `file`.
+///
+/// # Platform-specific behavior
+///
+/// On Linux and Android, this uses the `STATX_DIOALIGN` fields of `statx`.
+/// On kernels that do not report them, the logical block size of the
+/// underlying block device is used for both alignments. Fails with
+/// `ErrorKind::Unsupported` if the file does not support direct I/O, and on
+/// other platforms.
+pub fn direct_io_alignment(file: &File) -> io::Result<Alignment> {
+    sys::direct_io_alignment(file)
+}
+
+/// A file opened for direct I/O, bypassing the page cache.
+///
+/// Direct I/O requires file offsets, buffer lengths and buffer addresses to
+/// be suitably aligned, otherwise the operating system fails with an
+/// unhelpful `EINVAL`. `DirectFile` checks the alignment of every request
+/// up front and fails with an error of kind `ErrorKind::InvalidInput`
+/// describing the problem instead. Use `AlignedBuf` to obtain suitably
+/// aligned buffers.
+///
+/// ```no_run
+/// use file_offset::{DirectFile, ReadAt};
+/// use std::fs::OpenOptions;
+///
+/// let file = DirectFile::open("disk.img", OpenOptions::new().read(true)).unwrap();
+/// let mut buf = file.alloc(4 * file.alignment().offset);
+/// file.read_exact_offset(&mut buf, 0).unwrap();
+/// ```
+#[derive(Debug)]
+pub struct DirectFile {
+    file: File,
+    alignment: Alignment,
+}
+
+impl DirectFile {
+    /// Opens a file for direct I/O with the given options.
+    ///
+    /// # Platform-specific behavior
+    ///
+    /// On Linux and Android, this adds `O_DIRECT` to the options, replacing
+    /// any custom flags. Fails with `ErrorKind::Unsupported` on other
+    /// platforms.
+    pub fn open<P: AsRef<Path>>(path: P, options: &OpenOptions) -> io::Result<DirectFile> {
+        let mut options = options.clone();
+        sys::set_direct(&mut options)?;
+        DirectFile::new(options.open(path)?)
+    }
+
+    /// Wraps a file already opened for direct I/O, determining its alignment
+    /// using `direct_io_alignment`.
+    pub fn new(file: File) -> io::Result<DirectFile> {
+        let alignment = direct_io_alignment(&file)?;
+        Ok(DirectFile::with_alignment(file, alignment))
+    }
+
+    /// Wraps a file already opened for direct I/O, using the given
+    /// alignment.
+    ///
+    /// # Panics
+    ///
+    /// Panics if either alignment is not a power of two.
+    pub fn with_alignment(file: File, alignment: Alignment) -> DirectFile {
+        assert!(
+            alignment.memory.is_power_of_two() && alignment.offset.is_power_of_two(),
+            "direct I/O alignment must be a power of two"
+        );
+        DirectFile { file, alignment }
+    }
+
+    /// Returns the alignment required by this file.
+    pub fn alignment(&self) -> Alignment {
+        self.alignment
+    }
+
+    /// Allocates a zeroed buffer of `len` bytes aligned for this file.
+    ///
+    /// `len` still needs to be a multiple of `alignment().offset` for the
+    /// buffer to be usable in a single request.
+    pub fn alloc(&self, len: usize) -> AlignedBuf {
+        AlignedBuf::new(len, self.alignment.memory)
+    }
+
+    /// Returns a reference to the underlying file.
+    pub fn get_ref(&self) -> &File {
+        &self.file
+    }
+
+    /// Consumes the `DirectFile`, returning the underlying file.
+    pub fn into_inner(self) -> File {
+        self.file
+    }
+
+    fn check_offset(&self, offset: u64) -> io::Result<()> {
+        if !offset.is_multiple_of(self.alignment.offset as u64) {
+            return Err(misaligned("offset", offset, self.alignment.offset));
+        }
+        Ok(())
+    }
+
+    fn check_buf(&self, buf: &[u8]) -> io::Result<()> {
+        if !buf.len().is_multiple_of(self.alignment.offset) {
+            return Err(misaligned(
+                "buffer length",
+                buf.len() as u64,
+                self.alignment.offset,
+            ));
+        }
+        if !(buf.as_ptr() as usize).is_multiple_of(self.alignment.memory) {
+            return Err(misaligned(
+                "buffer address",
+                buf.as_ptr() as usize as u64,
+                self.alignment.memory,
+            ));
+        }
+        Ok(())
+    }
+
+    fn check(&self, buf: &[u8], offset: u64) -> io::Result<()> {
+        self.check_offset(offset)?;
+        self.check_buf(buf)
+    }
+
+    // Writes zeros from an aligned buffer, for file systems that cannot zero
+    // ranges.
+    fn write_zeros(&self, mut offset: u64, mut len: u64) -> io::Result<()> {
+        self.check_offset(offset)?;
+        if !len.is_multiple_of(self.alignment.offset as u64) {
+            return Err(misaligned("length", len, self.alignment.offset));
+        }
+        let chunk = cmp::max(BUFFER_SIZE, self.alignment.offset);
+        let zeros = self.alloc(cmp::min(len, chunk as u64) as usize);
+        while len > 0 {
+            let n = cmp::min(len, zeros.len() as u64) as usize;
+            self.write_all_offset(&zeros[..n], offset)?;
+            offset += n as u64;
+            len -= n as u64;
+        }
+        Ok(())
+    }
+}
+
+fn misaligned(what: &str, value: u64, align: usize) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!(
+            "direct I/O {} {} is not a multiple of the required alignment {}",
+            what, value, align
+        ),
+    )
+}
+
+impl ReadAt for DirectFile {
+    fn read_offset(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        self.check(buf, offset)?;
+        self.file.read_offset(buf, offset)
+    }
+    fn size(&self) -> io::Result<u64> {
+        self.file.size()
+    }
+    fn read_offset_with_flags(
+        &self,
+        buf: &mut [u8],
+        offset: u64,
+        flags: RwFlags,
+    ) -> io::Result<usize> {
+        self.check(buf, offset)?;
+        self.file.read_offset_with_flags(buf, offset, flags)
+    }
+    /// A short read that ends at an unaligned offset can only be caused by
+    /// the end of the file, so it is reported as such instead of continuing
+    /// with a misaligned request.
+    fn read_exact_offset(&self, mut buf: &mut [u8], mut offset: u64) -> Result<(), TransferError> {
+        self.check(buf, offset)
+            .map_err(|e| TransferError::new(0, e))?;
+        let mut transferred = 0;
+        while !buf.is_empty() && offset.is_multiple_of(self.alignment.offset as u64) {
+            match self.file.read_offset(buf, offset) {
+                Ok(0) => break,
+                Ok(n) => {
+                    let tmp = buf;
+                    buf = &mut tmp[n..];
+                    offset += n as u64;
+                    transferred += n;
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(TransferError::new(transferred, e)),
+            }
+        }
+        if !buf.is_empty() {
+            return Err(TransferError::new(
+                transferred,
+                io::Error::new(io::ErrorKind::UnexpectedEof, "failed to fill whole buffer"),
+            ));
+        }
+        Ok(())
+    }
+    fn read_vectored_offset(&self, bufs: &mut [IoSliceMut], offset: u64) -> io::Result<usize> {
+        self.check_offset(offset)?;
+        for buf in bufs.iter() {
+            self.check_buf(buf)?;
+        }
+        self.file.read_vectored_offset(bufs, offset)
+    }
+    fn read_offsets_batch(&self, reqs: &mut [(u64, &mut [u8])]) -> Vec<io::Result<usize>> {
+        let reqs =
+            reqs.iter_mut()
+                .map(|&mut (offset, ref mut buf)| match self.check(buf, offset) {
+                    Ok(()) => Ok((offset, &mut **buf)),
+                    Err(e) => Err(Err(e)),
+                });
+        batch::forward_batch(reqs, |reqs| self.file.read_offsets_batch(reqs))
+    }
+    fn advise_range(&self, offset: u64, len: u64, advice: Advice) -> io::Result<()> {
+        self.file.advise_range(offset, len, advice)
+    }
+    fn readahead(&self, offset: u64, len: u64) -> io::Result<()> {
+        self.file.readahead(offset, len)
+    }
+}
+
+impl WriteAt for DirectFile {
+    fn write_offset(&self, buf: &[u8], offset: u64) -> io::Result<usize> {
+        self.check(buf, offset)?;
+        self.file.write_offset(buf, offset)
+    }
+    fn set_size(&self, size: u64) -> io::Result<()> {
+        self.file.set_size(size)
+    }
+    fn write_offset_with_flags(
+        &self,
+        buf: &[u8],
+        offset: u64,
+        flags: RwFlags,
+    ) -> io::Result<usize> {
+        self.check(buf, offset)?;
+        self.file.write_offset_with_flags(buf, offset, flags)
+    }
+    fn write_vectored_offset(&self, bufs: &[IoSlice], offset: u64) -> io::Result<usize> {
+        self.check_offset(offset)?;
+        for buf in bufs {
+            self.check_buf(buf)?;
+        }
+        self.file.write_vectored_offset(bufs, offset)
+    }
+    fn write_offsets_batch(&self, reqs: &[(u64, &[u8])]) -> Vec<io::Result<usize>> {
+        let reqs = reqs
+            .iter()
+            .map(|&(offset, buf)| match self.check(buf, offset) {
+                Ok(()) => Ok((offset, buf)),
+                Err(e) => Err(Err(e)),
+            });
+        batch::forward_batch(reqs, |reqs| self.file.write_offsets_batch(reqs))
+    }
+    fn allocate_range(&self, offset: u64, len: u64) -> io::Result<()> {
+        self.file.allocate_range(offset, len)
+    }
+    /// Unlike for `File`, this is not emulated by writing zeros if the file
+    /// system does not support it.
+    fn punch_hole(&self, offset: u64, len: u64) -> io::Result<()> {
+        sys::fallocate(&self.file, sys::Fallocate::PunchHole, offset, len)
+    }
+    /// If the file system does not support this, zeros are written from an
+    /// aligned buffer, which requires `offset` and `len` to be aligned.
+    fn zero_range(&self, offset: u64, len: u64) -> io::Result<()> {
+        match sys::fallocate(&self.file, sys::Fallocate::ZeroRange, offset, len) {
+            Err(ref e) if e.kind() == io::ErrorKind::Unsupported => self.write_zeros(offset, len),
+            result => result,
+        }
+    }
+    fn collapse_range(&self, offset: u64, len: u64) -> io::Result<()> {
+        self.file.collapse_range(offset, len)
+    }
+    fn insert_range(&self, offset: u64, len: u64) -> io::Result<()> {
+        self.file.insert_range(offset, len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::{AlignedBuf, Alignment, DirectFile};
+    use test_util::temp_file;
+    use {ReadAt, WriteAt};
+
+    const ALIGNMENT: Alignment = Alignment {
+        memory: 64,
+        offset: 512,
+    };
+
+    // The alignment is only checked by `DirectFile` itself, as the file is
+    // not opened for direct I/O.
+    fn file(contents: &[u8]) -> DirectFile {
+        DirectFile::with_alignment(temp_file(contents), ALIGNMENT)
+    }
+
+    fn assert_misaligned(err: io::Error, message: &str) {
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(
+            err.to_string()
+                .starts_with(&format!("direct I/O {}", message)),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn aligned_buf() {
+        for &(len, align) in &[(1000, 4096), (512, 64), (0, 512), (1, 1)] {
+            let buf = AlignedBuf::new(len, align);
+            assert_eq!(buf.len(), len);
+            assert_eq!(buf.align(), align);
+            assert_eq!(buf.as_ptr() as usize % align, 0);
+            assert!(buf.iter().all(|&b| b == 0));
+        }
+        let mut buf = file(b"").alloc(1024);
+        assert_eq!(buf.align(), ALIGNMENT.memory);
+        buf[3] = 7;
+        let clone = buf.clone();
+        assert_eq!(clone.as_ptr() as usize % ALIGNMENT.memory, 0);
+        assert_eq!(&clone[..], &buf[..]);
+    }
+
+    #[test]
+    fn misaligned_requests() {
+        let file = file(&[1; 2048]);
+        let mut buf = file.alloc(1025);
+        let err = file.read_offset(&mut buf[..512], 100).unwrap_err();
+        assert_misaligned(err, "offset 100 ");
+        let err = file.write_offset(&buf[..100], 512).unwrap_err();
+        assert_misaligned(err, "buffer length 100 ");
+        let err = file.read_offset(&mut buf[1..513], 512).unwrap_err();
+        assert_misaligned(err, "buffer address ");
+        let err = file.read_exact_offset(&mut buf[..100], 0).unwrap_err();
+        assert_eq!(err.transferred(), 0);
+        assert_misaligned(err.into_error(), "buffer length 100 ");
+
+        let (first, second) = buf.split_at_mut(512);
+        let results = file.read_offsets_batch(&mut [(512, first), (1, &mut second[..512])]);
+        assert_eq!(results[0].as_ref().unwrap(), &512);
+        assert_eq!(
+            results[1].as_ref().unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+        assert!(buf[..512].iter().all(|&b| b == 1));
+    }
+
+    #[test]
+    fn read_exact_to_unaligned_end() {
+        let file = file(&[1; 700]);
+        let mut buf = file.alloc(1024);
+        let err = file.read_exact_offset(&mut buf, 0).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(err.transferred(), 700);
+    }
+
+    #[test]
+    fn zero_range() {
+        let file = file(&[1; 2048]);
+        file.zero_range(0, 512).unwrap();
+        file.write_zeros(1024, 512).unwrap();
+        assert_misaligned(file.write_zeros(100, 512).unwrap_err(), "offset 100 ");
+        assert_misaligned(file.write_zeros(512, 100).unwrap_err(), "length 100 ");
+        let mut buf = file.alloc(2048);
+        file.read_exact_offset(&mut buf, 0).unwrap();
+        assert!(buf[..512].iter().all(|&b| b == 0));
+        assert!(buf[512..1024].iter().all(|&b| b == 1));
+        assert!(buf[1024..1536].iter().all(|&b| b == 0));
+        assert!(buf[1536..].iter().all(|&b| b == 1));
+    }
+}
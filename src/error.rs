@@ -0,0 +1,71 @@
+use std::error;
+use std::fmt;
+use std::io;
+
+/// The error type returned by operations that may make partial progress
+/// before failing, such as `ReadAt::read_exact_offset` and
+/// `WriteAt::write_all_offset`.
+///
+/// In addition to the underlying `io::Error`, it records how many bytes were
+/// transferred successfully before the failure occurred. These bytes are
+/// located at the start of the buffer passed to the operation, and at the
+/// start of the requested file range.
+///
+/// It can be converted into an `io::Error` of the same kind, so the `?`
+/// operator can be used in functions returning `io::Result`. The original
+/// `TransferError` can then be recovered using `io::Error::get_ref`.
+#[derive(Debug)]
+pub struct TransferError {
+    transferred: usize,
+    error: io::Error,
+}
+
+impl TransferError {
+    /// Creates a new error from the number of bytes transferred and the
+    /// underlying I/O error.
+    pub fn new(transferred: usize, error: io::Error) -> TransferError {
+        TransferError { transferred, error }
+    }
+
+    /// Returns the number of bytes transferred before the error occurred.
+    pub fn transferred(&self) -> usize {
+        self.transferred
+    }
+
+    /// Returns the kind of the underlying I/O error.
+    pub fn kind(&self) -> io::ErrorKind {
+        self.error.kind()
+    }
+
+    /// Returns a reference to the underlying I/O error.
+    pub fn error(&self) -> &io::Error {
+        &self.error
+    }
+
+    /// Consumes the error, returning the underlying I/O error.
+    pub fn into_error(self) -> io::Error {
+        self.error
+    }
+}
+
+impl fmt::Display for TransferError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} (after transferring {} bytes)",
+            self.error, self.transferred
+        )
+    }
+}
+
+impl error::Error for TransferError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+impl From<TransferError> for io::Error {
+    fn from(err: TransferError) -> io::Error {
+        io::Error::new(err.kind(), err)
+    }
+}
@@ -0,0 +1,321 @@
+use std::cmp;
+use std::fs::File;
+use std::io;
+use std::ops::{Bound, RangeBounds};
+
+use sys;
+
+/// Whether a `Segment` contains data or is a hole.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SegmentKind {
+    /// The segment may contain data.
+    Data,
+    /// The segment is a hole, which reads as zeros.
+    Hole,
+}
+
+/// A contiguous range of a file yielded by `DataExtents`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Segment {
+    /// The offset of the start of the segment.
+    pub offset: u64,
+    /// The length of the segment in bytes.
+    pub len: u64,
+    /// Whether the segment contains data or is a hole.
+    pub kind: SegmentKind,
+}
+
+/// An iterator over the data and holes of a file, created by `data_extents`.
+#[derive(Debug)]
+pub struct DataExtents {
+    // `None` if the holes cannot be determined, in which case the rest of
+    // the range is reported as data.
+    file: Option<File>,
+    pos: u64,
+    end: u64,
+}
+
+/// Returns an iterator over the data and holes of `file` within `range`.
+///
+/// The iterator yields consecutive, non-overlapping segments covering the
+/// part of the range that lies within the file, in increasing order of
+/// offset. This allows skipping the holes of sparse files when copying or
+/// checksumming them, as holes always read as zeros.
+///
+/// Segments reported as data may still contain holes or zeros, and the
+/// result is only a snapshot if the file is modified concurrently. The
+/// cursor of `file` is not affected.
+///
+/// # Platform-specific behavior
+///
+/// On Linux and Android, this uses `lseek` with `SEEK_DATA` and `SEEK_HOLE`
+/// on a separately opened file description, obtained via `/proc/self/fd`.
+/// If `file` is not a regular file, that is not possible, or the file
+/// system does not report holes, the whole range is reported as a single
+/// data segment, which is always the case on other platforms.
+///
+/// ```no_run
+/// use file_offset::{data_extents, SegmentKind};
+/// use std::fs::File;
+///
+/// let file = File::open("disk.img").unwrap();
+/// for segment in data_extents(&file, ..).unwrap() {
+///     let segment = segment.unwrap();
+///     if segment.kind == SegmentKind::Data {
+///         println!("data at {}, {} bytes", segment.offset, segment.len);
+///     }
+/// }
+/// ```
+pub fn data_extents<R: RangeBounds<u64>>(file: &File, range: R) -> io::Result<DataExtents> {
+    let size = file.metadata()?.len();
+    let start = match range.start_bound() {
+        Bound::Included(&start) => start,
+        Bound::Excluded(&start) => start.saturating_add(1),
+        Bound::Unbounded => 0,
+    };
+    let end = match range.end_bound() {
+        Bound::Included(&end) => end.saturating_add(1),
+        Bound::Excluded(&end) => end,
+        Bound::Unbounded => u64::MAX,
+    };
+    Ok(DataExtents {
+        file: sys::reopen(file).ok(),
+        pos: start,
+        end: cmp::min(end, size),
+    })
+}
+
+impl DataExtents {
+    fn segment(&mut self, end: u64, kind: SegmentKind) -> Segment {
+        let end = cmp::min(end, self.end);
+        let segment = Segment {
+            offset: self.pos,
+            len: end - self.pos,
+            kind,
+        };
+        self.pos = end;
+        segment
+    }
+
+    fn next_segment(&mut self, file: &File) -> io::Result<Segment> {
+        let data = match sys::seek_extent(file, self.pos, true)? {
+            Some(data) => data,
+            // There is no data after `pos`.
+            None => return Ok(self.segment(self.end, SegmentKind::Hole)),
+        };
+        if data > self.pos {
+            return Ok(self.segment(data, SegmentKind::Hole));
+        }
+        match sys::seek_extent(file, self.pos, false)? {
+            Some(hole) if hole > self.pos => Ok(self.segment(hole, SegmentKind::Data)),
+            // The file was truncated concurrently.
+            _ => Ok(self.segment(self.end, SegmentKind::Hole)),
+        }
+    }
+}
+
+impl Iterator for DataExtents {
+    type Item = io::Result<Segment>;
+    fn next(&mut self) -> Option<io::Result<Segment>> {
+        if self.pos >= self.end {
+            return None;
+        }
+        let file = match self.file.take() {
+            Some(file) => file,
+            None => return Some(Ok(self.segment(self.end, SegmentKind::Data))),
+        };
+        let result = self.next_segment(&file);
+        match result {
+            Err(ref e) if e.kind() == io::ErrorKind::Unsupported => {
+                return Some(Ok(self.segment(self.end, SegmentKind::Data)));
+            }
+            Err(_) => self.end = self.pos,
+            Ok(_) => self.file = Some(file),
+        }
+        Some(result)
+    }
+}
+
+/// A physical extent of a file, as returned by `extent_map`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Extent {
+    /// The offset of the extent in the file.
+    pub logical: u64,
+    /// The offset of the extent on the storage device.
+    ///
+    /// This is meaningless if `flags` contains `ExtentFlags::UNKNOWN`.
+    pub physical: u64,
+    /// The length of the extent in bytes.
+    pub len: u64,
+    /// Further information about the extent.
+    pub flags: ExtentFlags,
+}
+
+flags! {
+    /// Flags describing an `Extent`.
+    ///
+    /// These correspond to the `FIEMAP_EXTENT_*` flags on Linux.
+    pub struct ExtentFlags: "FIEMAP_EXTENT_*";
+
+    /// The location of the data is not known (`FIEMAP_EXTENT_UNKNOWN`).
+    const UNKNOWN = 0;
+    /// Storage for the extent is not allocated yet
+    /// (`FIEMAP_EXTENT_DELALLOC`). Implies `UNKNOWN`.
+    const DELALLOC = 1;
+    /// The data is compressed or otherwise encoded
+    /// (`FIEMAP_EXTENT_ENCODED`), so it cannot be read from the device
+    /// directly.
+    const ENCODED = 2;
+    /// The data is encrypted (`FIEMAP_EXTENT_DATA_ENCRYPTED`). Implies
+    /// `ENCODED`.
+    const ENCRYPTED = 3;
+    /// The offsets of the extent are not block-aligned
+    /// (`FIEMAP_EXTENT_NOT_ALIGNED`).
+    const NOT_ALIGNED = 4;
+    /// The data is stored within a metadata block
+    /// (`FIEMAP_EXTENT_DATA_INLINE`). Implies `NOT_ALIGNED`.
+    const INLINE = 5;
+    /// The data is packed into a block shared with other files
+    /// (`FIEMAP_EXTENT_DATA_TAIL`). Implies `NOT_ALIGNED`.
+    const TAIL = 6;
+    /// Storage is allocated, but not written to yet, so the extent reads
+    /// as zeros (`FIEMAP_EXTENT_UNWRITTEN`).
+    const UNWRITTEN = 7;
+    /// The file system does not track extents, the extent is a block
+    /// (`FIEMAP_EXTENT_MERGED`).
+    const MERGED = 8;
+    /// The storage may be shared with other files, e.g. through reflinks or
+    /// snapshots (`FIEMAP_EXTENT_SHARED`).
+    const SHARED = 9;
+}
+
+/// Returns the physical extents of `file` that overlap the range
+/// `[offset, offset + len)`.
+///
+/// The extents are sorted by their logical offset. The first and last
+/// extent may extend beyond the range, and holes are not reported. Passing
+/// `u64::MAX` as `len` maps the whole file from `offset` on.
+///
+/// This is mainly useful for diagnostics, or to determine whether two files
+/// share storage. Data that was written but not yet flushed may be reported
+/// with `ExtentFlags::DELALLOC` and no physical location.
+///
+/// # Platform-specific behavior
+///
+/// This uses the `FS_IOC_FIEMAP` ioctl on Linux and Android. It fails with
+/// `ErrorKind::Unsupported` if the file system does not support it, and on
+/// other platforms.
+pub fn extent_map(file: &File, offset: u64, len: u64) -> io::Result<Vec<Extent>> {
+    let end = offset.saturating_add(len);
+    let mut extents = Vec::new();
+    let mut pos = offset;
+    while pos < end {
+        let mapped = extents.len();
+        let last = sys::fiemap(file, pos, end - pos, &mut extents)?;
+        let next = match extents[mapped..].last() {
+            Some(extent) => extent.logical.saturating_add(extent.len),
+            None => break,
+        };
+        if last || next <= pos {
+            break;
+        }
+        pos = next;
+    }
+    Ok(extents)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use super::{data_extents, extent_map, ExtentFlags, Segment, SegmentKind};
+    use test_util::temp_file;
+    use WriteAt;
+
+    const MIB: u64 = 1 << 20;
+    const DATA: usize = 64 * 1024;
+
+    // Creates a 3 MiB file with data at `[0, 64K)` and `[1M, 1M + 64K)`.
+    fn sparse_file() -> File {
+        let file = temp_file(&[1; DATA]);
+        file.write_all_offset(&[2; DATA], MIB).unwrap();
+        file.set_size(3 * MIB).unwrap();
+        file
+    }
+
+    fn segments(file: &File, start: u64, end: u64) -> Vec<(u64, u64, SegmentKind)> {
+        data_extents(file, start..end)
+            .unwrap()
+            .map(|s| {
+                let Segment { offset, len, kind } = s.unwrap();
+                (offset, offset + len, kind)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn data_and_holes() {
+        use self::SegmentKind::{Data, Hole};
+        let file = sparse_file();
+        let all = segments(&file, 0, u64::MAX);
+        if all.len() == 1 {
+            // The platform or file system does not report holes.
+            assert_eq!(all, [(0, 3 * MIB, Data)]);
+            return;
+        }
+        let data = DATA as u64;
+        assert_eq!(
+            all,
+            [
+                (0, data, Data),
+                (data, MIB, Hole),
+                (MIB, MIB + data, Data),
+                (MIB + data, 3 * MIB, Hole),
+            ]
+        );
+        assert_eq!(
+            segments(&file, 1000, MIB + 100),
+            [
+                (1000, data, Data),
+                (data, MIB, Hole),
+                (MIB, MIB + 100, Data)
+            ]
+        );
+        assert_eq!(data_extents(&file, ..).unwrap().count(), 4);
+        assert_eq!(
+            segments(&file, 2 * MIB, 10 * MIB),
+            [(2 * MIB, 3 * MIB, Hole)]
+        );
+        assert_eq!(segments(&file, 3 * MIB, 10 * MIB), []);
+        assert_eq!(segments(&file, 100, 100), []);
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    #[test]
+    fn written_extents() {
+        let file = sparse_file();
+        file.sync_all().unwrap();
+        let extents = extent_map(&file, 0, u64::MAX).unwrap();
+        assert!(!extents.is_empty());
+        assert_eq!(extents[0].logical, 0);
+        assert!(extents[0].len >= DATA as u64);
+        assert!(!extents[0].flags.contains(ExtentFlags::UNKNOWN));
+        let last = extents.last().unwrap();
+        assert!(last.logical <= MIB && last.logical + last.len >= MIB + DATA as u64);
+        for pair in extents.windows(2) {
+            assert!(pair[0].logical + pair[0].len <= pair[1].logical);
+        }
+
+        let extents = extent_map(&file, MIB + 100, 1).unwrap();
+        assert_eq!(extents.len(), 1);
+        let extent = extents[0];
+        assert!(extent.logical <= MIB + 100 && extent.logical + extent.len > MIB + 100);
+        assert_eq!(
+            extent.physical - last.physical,
+            extent.logical - last.logical
+        );
+        assert!(extent_map(&file, DATA as u64, MIB - DATA as u64)
+            .unwrap()
+            .is_empty());
+    }
+}
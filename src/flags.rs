@@ -0,0 +1,41 @@
+use std::io;
+
+flags! {
+    /// Per-call flags for `ReadAt::read_offset_with_flags` and
+    /// `WriteAt::write_offset_with_flags`.
+    ///
+    /// Flags can be combined using the `|` operator.
+    ///
+    /// # Platform-specific behavior
+    ///
+    /// These flags correspond to the `RWF_*` flags of the `preadv2` and
+    /// `pwritev2` functions on Linux. Other platforms support none of them.
+    pub struct RwFlags: "RWF_*";
+
+    /// High priority request, poll if possible (`RWF_HIPRI`).
+    ///
+    /// Only has an effect on files opened with `O_DIRECT`.
+    const HIPRI = 0;
+    /// Per-write equivalent of `O_DSYNC` (`RWF_DSYNC`).
+    const DSYNC = 1;
+    /// Per-write equivalent of `O_SYNC` (`RWF_SYNC`).
+    const SYNC = 2;
+    /// Do not wait for data which is not immediately available
+    /// (`RWF_NOWAIT`).
+    ///
+    /// If the data would have to be read from the storage device, or if a
+    /// write would block, the call fails with `ErrorKind::WouldBlock`.
+    const NOWAIT = 3;
+    /// Per-write equivalent of `O_APPEND` (`RWF_APPEND`).
+    ///
+    /// The data is written to the end of the file, the offset argument only
+    /// determines the position for files that don't support appending.
+    const APPEND = 4;
+}
+
+pub(crate) fn unsupported() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "per-call I/O flags are not supported on this platform",
+    )
+}
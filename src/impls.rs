@@ -0,0 +1,463 @@
+use std::cell::RefCell;
+use std::cmp;
+use std::io;
+use std::io::{Cursor, IoSlice, IoSliceMut};
+use std::iter;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+
+use space;
+use sys::Fallocate;
+use {lock_ignoring_poison, Advice, ReadAt, ReadBufCursor, RwFlags, TransferError, WriteAt};
+
+macro_rules! forward_read_at {
+    () => {
+        #[inline]
+        fn read_offset(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+            (**self).read_offset(buf, offset)
+        }
+        #[inline]
+        fn size(&self) -> io::Result<u64> {
+            (**self).size()
+        }
+        #[inline]
+        fn read_offset_with_flags(
+            &self,
+            buf: &mut [u8],
+            offset: u64,
+            flags: RwFlags,
+        ) -> io::Result<usize> {
+            (**self).read_offset_with_flags(buf, offset, flags)
+        }
+        #[inline]
+        fn read_exact_offset(&self, buf: &mut [u8], offset: u64) -> Result<(), TransferError> {
+            (**self).read_exact_offset(buf, offset)
+        }
+        #[inline]
+        fn read_buf_offset(&self, buf: ReadBufCursor, offset: u64) -> io::Result<()> {
+            (**self).read_buf_offset(buf, offset)
+        }
+        #[inline]
+        fn read_to_vec_offset(
+            &self,
+            vec: &mut Vec<u8>,
+            offset: u64,
+            len: usize,
+        ) -> io::Result<usize> {
+            (**self).read_to_vec_offset(vec, offset, len)
+        }
+        #[inline]
+        fn read_vectored_offset(&self, bufs: &mut [IoSliceMut], offset: u64) -> io::Result<usize> {
+            (**self).read_vectored_offset(bufs, offset)
+        }
+        #[inline]
+        fn read_exact_vectored_offset(
+            &self,
+            bufs: &mut [IoSliceMut],
+            offset: u64,
+        ) -> Result<(), TransferError> {
+            (**self).read_exact_vectored_offset(bufs, offset)
+        }
+        #[inline]
+        fn read_offsets_batch(&self, reqs: &mut [(u64, &mut [u8])]) -> Vec<io::Result<usize>> {
+            (**self).read_offsets_batch(reqs)
+        }
+        #[inline]
+        fn advise_range(&self, offset: u64, len: u64, advice: Advice) -> io::Result<()> {
+            (**self).advise_range(offset, len, advice)
+        }
+        #[inline]
+        fn readahead(&self, offset: u64, len: u64) -> io::Result<()> {
+            (**self).readahead(offset, len)
+        }
+    };
+}
+
+macro_rules! forward_write_at {
+    () => {
+        #[inline]
+        fn write_offset(&self, buf: &[u8], offset: u64) -> io::Result<usize> {
+            (**self).write_offset(buf, offset)
+        }
+        #[inline]
+        fn set_size(&self, size: u64) -> io::Result<()> {
+            (**self).set_size(size)
+        }
+        #[inline]
+        fn write_offset_with_flags(
+            &self,
+            buf: &[u8],
+            offset: u64,
+            flags: RwFlags,
+        ) -> io::Result<usize> {
+            (**self).write_offset_with_flags(buf, offset, flags)
+        }
+        #[inline]
+        fn write_all_offset(&self, buf: &[u8], offset: u64) -> Result<(), TransferError> {
+            (**self).write_all_offset(buf, offset)
+        }
+        #[inline]
+        fn write_vectored_offset(&self, bufs: &[IoSlice], offset: u64) -> io::Result<usize> {
+            (**self).write_vectored_offset(bufs, offset)
+        }
+        #[inline]
+        fn write_all_vectored_offset(
+            &self,
+            bufs: &mut [IoSlice],
+            offset: u64,
+        ) -> Result<(), TransferError> {
+            (**self).write_all_vectored_offset(bufs, offset)
+        }
+        #[inline]
+        fn write_offsets_batch(&self, reqs: &[(u64, &[u8])]) -> Vec<io::Result<usize>> {
+            (**self).write_offsets_batch(reqs)
+        }
+        #[inline]
+        fn allocate_range(&self, offset: u64, len: u64) -> io::Result<()> {
+            (**self).allocate_range(offset, len)
+        }
+        #[inline]
+        fn punch_hole(&self, offset: u64, len: u64) -> io::Result<()> {
+            (**self).punch_hole(offset, len)
+        }
+        #[inline]
+        fn zero_range(&self, offset: u64, len: u64) -> io::Result<()> {
+            (**self).zero_range(offset, len)
+        }
+        #[inline]
+        fn collapse_range(&self, offset: u64, len: u64) -> io::Result<()> {
+            (**self).collapse_range(offset, len)
+        }
+        #[inline]
+        fn insert_range(&self, offset: u64, len: u64) -> io::Result<()> {
+            (**self).insert_range(offset, len)
+        }
+    };
+}
+
+impl<T: ReadAt + ?Sized> ReadAt for &T {
+    forward_read_at!();
+}
+
+impl<T: ReadAt + ?Sized> ReadAt for &mut T {
+    forward_read_at!();
+}
+
+impl<T: ReadAt + ?Sized> ReadAt for Box<T> {
+    forward_read_at!();
+}
+
+impl<T: ReadAt + ?Sized> ReadAt for Rc<T> {
+    forward_read_at!();
+}
+
+impl<T: ReadAt + ?Sized> ReadAt for Arc<T> {
+    forward_read_at!();
+}
+
+impl<T: WriteAt + ?Sized> WriteAt for &T {
+    forward_write_at!();
+}
+
+impl<T: WriteAt + ?Sized> WriteAt for &mut T {
+    forward_write_at!();
+}
+
+impl<T: WriteAt + ?Sized> WriteAt for Box<T> {
+    forward_write_at!();
+}
+
+impl<T: WriteAt + ?Sized> WriteAt for Rc<T> {
+    forward_write_at!();
+}
+
+impl<T: WriteAt + ?Sized> WriteAt for Arc<T> {
+    forward_write_at!();
+}
+
+impl ReadAt for [u8] {
+    fn read_offset(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        if offset >= self.len() as u64 {
+            return Ok(0);
+        }
+        let data = &self[offset as usize..];
+        let n = cmp::min(buf.len(), data.len());
+        buf[..n].copy_from_slice(&data[..n]);
+        Ok(n)
+    }
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.len() as u64)
+    }
+    fn read_buf_offset(&self, mut buf: ReadBufCursor, offset: u64) -> io::Result<()> {
+        if offset >= self.len() as u64 {
+            return Ok(());
+        }
+        let data = &self[offset as usize..];
+        let n = cmp::min(buf.capacity(), data.len());
+        buf.put_slice(&data[..n]);
+        Ok(())
+    }
+}
+
+impl ReadAt for Vec<u8> {
+    #[inline]
+    fn read_offset(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        self[..].read_offset(buf, offset)
+    }
+    #[inline]
+    fn size(&self) -> io::Result<u64> {
+        self[..].size()
+    }
+    #[inline]
+    fn read_buf_offset(&self, buf: ReadBufCursor, offset: u64) -> io::Result<()> {
+        self[..].read_buf_offset(buf, offset)
+    }
+}
+
+/// Reads from the underlying buffer, ignoring the cursor position.
+impl<T: AsRef<[u8]>> ReadAt for Cursor<T> {
+    #[inline]
+    fn read_offset(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        self.get_ref().as_ref().read_offset(buf, offset)
+    }
+    #[inline]
+    fn size(&self) -> io::Result<u64> {
+        self.get_ref().as_ref().size()
+    }
+    #[inline]
+    fn read_buf_offset(&self, buf: ReadBufCursor, offset: u64) -> io::Result<()> {
+        self.get_ref().as_ref().read_buf_offset(buf, offset)
+    }
+}
+
+fn cvt_size(size: u64) -> io::Result<usize> {
+    if size > usize::MAX as u64 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "size exceeds the address space",
+        ));
+    }
+    Ok(size as usize)
+}
+
+// Writes `buf` into `vec` at `offset`, growing `vec` as needed. If `offset`
+// lies beyond the end of `vec`, the gap is filled with zeros.
+fn write_vec(vec: &mut Vec<u8>, buf: &[u8], offset: u64) -> io::Result<usize> {
+    let end = offset
+        .checked_add(buf.len() as u64)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "offset overflow"))?;
+    let end = cvt_size(end)?;
+    let offset = offset as usize;
+    if vec.len() < end {
+        vec.resize(end, 0);
+    }
+    vec[offset..end].copy_from_slice(buf);
+    Ok(buf.len())
+}
+
+// Applies a `WriteAt` space management operation to `vec`. Holes are
+// represented by zeros.
+fn fallocate_vec(vec: &mut Vec<u8>, op: Fallocate, offset: u64, len: u64) -> io::Result<()> {
+    let end = cvt_size(space::checked_end(offset, len)?)?;
+    let offset = offset as usize;
+    match op {
+        Fallocate::Allocate => {
+            if vec.len() < end {
+                vec.resize(end, 0);
+            }
+        }
+        Fallocate::PunchHole => {
+            let end = cmp::min(end, vec.len());
+            if offset < end {
+                vec[offset..end].fill(0);
+            }
+        }
+        Fallocate::ZeroRange => {
+            if vec.len() < end {
+                vec.resize(end, 0);
+            }
+            vec[offset..end].fill(0);
+        }
+        Fallocate::CollapseRange => {
+            if end >= vec.len() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "collapsed range must end before the end of the data",
+                ));
+            }
+            vec.drain(offset..end);
+        }
+        Fallocate::InsertRange => {
+            if offset >= vec.len() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "inserted range must start before the end of the data",
+                ));
+            }
+            cvt_size(vec.len() as u64 + (end - offset) as u64)?;
+            vec.splice(offset..offset, iter::repeat_n(0, end - offset));
+        }
+    }
+    Ok(())
+}
+
+// Implements `ReadAt` and `WriteAt` for a type providing access to a
+// `Vec<u8>`, with `$this` bound to `self` in the expressions `$vec` and
+// `$vec_mut` borrowing the vector.
+macro_rules! vec_impls {
+    ($(#[$attr:meta])* $ty:ty, $this:ident => $vec:expr, $vec_mut:expr) => {
+        $(#[$attr])*
+        impl ReadAt for $ty {
+            fn read_offset(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+                let $this = self;
+                $vec.read_offset(buf, offset)
+            }
+            fn size(&self) -> io::Result<u64> {
+                let $this = self;
+                $vec.size()
+            }
+            fn read_buf_offset(&self, buf: ReadBufCursor, offset: u64) -> io::Result<()> {
+                let $this = self;
+                $vec.read_buf_offset(buf, offset)
+            }
+        }
+
+        $(#[$attr])*
+        impl WriteAt for $ty {
+            fn write_offset(&self, buf: &[u8], offset: u64) -> io::Result<usize> {
+                let $this = self;
+                write_vec($vec_mut, buf, offset)
+            }
+            fn set_size(&self, size: u64) -> io::Result<()> {
+                let size = cvt_size(size)?;
+                let $this = self;
+                $vec_mut.resize(size, 0);
+                Ok(())
+            }
+            fn allocate_range(&self, offset: u64, len: u64) -> io::Result<()> {
+                let $this = self;
+                fallocate_vec($vec_mut, Fallocate::Allocate, offset, len)
+            }
+            fn punch_hole(&self, offset: u64, len: u64) -> io::Result<()> {
+                let $this = self;
+                fallocate_vec($vec_mut, Fallocate::PunchHole, offset, len)
+            }
+            fn zero_range(&self, offset: u64, len: u64) -> io::Result<()> {
+                let $this = self;
+                fallocate_vec($vec_mut, Fallocate::ZeroRange, offset, len)
+            }
+            fn collapse_range(&self, offset: u64, len: u64) -> io::Result<()> {
+                let $this = self;
+                fallocate_vec($vec_mut, Fallocate::CollapseRange, offset, len)
+            }
+            fn insert_range(&self, offset: u64, len: u64) -> io::Result<()> {
+                let $this = self;
+                fallocate_vec($vec_mut, Fallocate::InsertRange, offset, len)
+            }
+        }
+    };
+}
+
+vec_impls! {
+    /// A `Vec<u8>` that grows when written past its end, similarly to a file.
+    RefCell<Vec<u8>>, this => this.borrow(), &mut this.borrow_mut()
+}
+
+vec_impls! {
+    /// A `Vec<u8>` that grows when written past its end, similarly to a file.
+    Mutex<Vec<u8>>, this => lock_ignoring_poison(this), &mut lock_ignoring_poison(this)
+}
+
+vec_impls! {
+    /// Accesses the underlying `Vec<u8>`, which grows when written past its
+    /// end, ignoring the cursor position.
+    ///
+    /// `Cursor<Vec<u8>>` itself only implements `ReadAt`, as writing at
+    /// offsets only requires a shared reference.
+    RefCell<Cursor<Vec<u8>>>, this => this.borrow().get_ref(), this.borrow_mut().get_mut()
+}
+
+vec_impls! {
+    /// Accesses the underlying `Vec<u8>`, which grows when written past its
+    /// end, ignoring the cursor position.
+    ///
+    /// `Cursor<Vec<u8>>` itself only implements `ReadAt`, as writing at
+    /// offsets only requires a shared reference.
+    Mutex<Cursor<Vec<u8>>>, this => lock_ignoring_poison(this).get_ref(), lock_ignoring_poison(this).get_mut()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::io::Cursor;
+    use std::sync::Mutex;
+
+    use {ReadAt, WriteAt};
+
+    #[test]
+    fn write_past_end_fills_gap_with_zeros() {
+        let data = RefCell::new(b"abc".to_vec());
+        assert_eq!(data.write_offset(b"xy", 6).unwrap(), 2);
+        assert_eq!(*data.borrow(), b"abc\0\0\0xy");
+        assert_eq!(data.size().unwrap(), 8);
+
+        let data = Mutex::new(Vec::new());
+        data.write_all_offset(b"z", 3).unwrap();
+        assert_eq!(*data.lock().unwrap(), b"\0\0\0z");
+    }
+
+    #[test]
+    fn write_inside_does_not_grow() {
+        let data = Mutex::new(b"abcdef".to_vec());
+        data.write_all_offset(b"XY", 2).unwrap();
+        assert_eq!(*data.lock().unwrap(), b"abXYef");
+    }
+
+    #[test]
+    fn read_past_end() {
+        let data = b"abc".to_vec();
+        let mut buf = [0xff; 4];
+        assert_eq!(data.read_offset(&mut buf, 3).unwrap(), 0);
+        assert_eq!(data.read_offset(&mut buf, u64::MAX).unwrap(), 0);
+        assert_eq!(data.read_offset(&mut buf, 1).unwrap(), 2);
+        assert_eq!(&buf, b"bc\xff\xff");
+        let err = data.read_exact_offset(&mut buf, 1).unwrap_err();
+        assert_eq!(err.transferred(), 2);
+
+        let mut vec = Vec::new();
+        assert_eq!(data.read_to_vec_offset(&mut vec, 10, 4).unwrap(), 0);
+        assert!(vec.is_empty());
+    }
+
+    #[test]
+    fn write_overflow() {
+        let data = RefCell::new(Vec::new());
+        assert!(data.write_offset(b"a", u64::MAX).is_err());
+        assert!(data.borrow().is_empty());
+    }
+
+    #[test]
+    fn set_size() {
+        let data = RefCell::new(b"abcdef".to_vec());
+        data.set_size(2).unwrap();
+        assert_eq!(*data.borrow(), b"ab");
+        data.set_size(4).unwrap();
+        assert_eq!(*data.borrow(), b"ab\0\0");
+    }
+
+    #[test]
+    fn cursor_ignores_position() {
+        let data = Mutex::new(Cursor::new(b"abc".to_vec()));
+        data.lock().unwrap().set_position(2);
+        data.write_all_offset(b"xy", 5).unwrap();
+        let mut buf = [0; 7];
+        data.read_exact_offset(&mut buf, 0).unwrap();
+        assert_eq!(&buf, b"abc\0\0xy");
+        assert_eq!(data.lock().unwrap().position(), 2);
+
+        let data = RefCell::new(Cursor::new(Vec::new()));
+        data.write_all_offset(b"a", 1).unwrap();
+        assert_eq!(data.size().unwrap(), 2);
+        assert_eq!(data.borrow().get_ref(), b"\0a");
+    }
+}
@@ -3,7 +3,7 @@
 //! writing files at specified offsets.
 //!
 //! ```
-//! use file_offset::FileExt;
+//! use file_offset::ReadAt;
 //! use std::fs::File;
 //! use std::str;
 //!
@@ -12,18 +12,115 @@
 //! f.read_offset(&mut buffer, 3);
 //! print!("{}", str::from_utf8(&buffer).unwrap());
 //! ```
+//!
+//! # Migrating from 0.1
+//!
+//! The methods of `FileExt` have moved to the `ReadAt` and `WriteAt` traits,
+//! and `FileExt` is now implemented for every type implementing both. As
+//! trait methods are only callable if their trait is in scope, code that only
+//! imports `FileExt` fails to compile with "no method named `read_offset`
+//! found". Import the traits providing the methods instead, or all of them at
+//! once through the prelude:
+//!
+//! ```
+//! use file_offset::prelude::*;
+//! use std::fs::File;
+//!
+//! let mut buffer = [0; 2048];
+//! let f = File::open("src/lib.rs").unwrap();
+//! f.read_offset(&mut buffer, 3).unwrap();
+//! ```
+//!
+//! Code implementing `FileExt` for its own types implements `ReadAt` and
+//! `WriteAt` instead, which additionally require `size` and `set_size`.
 
+use std::cmp;
 use std::fs::File;
 use std::io;
+use std::io::{IoSlice, IoSliceMut};
+use std::sync::{Mutex, MutexGuard};
+
+pub use self::advice::Advice;
+pub use self::append::{AppendError, Appender};
+#[cfg(feature = "blocking")]
+pub use self::async_ext::AsyncFile;
+pub use self::async_ext::{AsyncFileExt, BufFuture};
+pub use self::batch::{read_range_parallel, write_range_parallel};
+pub use self::buf::{ReadBuf, ReadBufCursor};
+pub use self::bytes::{ReadAtExt, WriteAtExt};
+pub use self::copy::copy_range;
+pub use self::cursor::OffsetCursor;
+pub use self::direct::{direct_io_alignment, AlignedBuf, Alignment, DirectFile};
+pub use self::error::TransferError;
+pub use self::extents::{
+    data_extents, extent_map, DataExtents, Extent, ExtentFlags, Segment, SegmentKind,
+};
+pub use self::flags::RwFlags;
+pub use self::lock::{lock_range, try_lock_range, LockKind, RangeLock};
+pub use self::lock_manager::{RangeGuard, RangeLockManager};
+pub use self::rmw::UnalignedFile;
+#[cfg(unix)]
+pub use self::send::send_range_to;
+pub use self::slice::FileSlice;
+#[cfg(all(feature = "io_uring", target_os = "linux"))]
+pub use self::uring::{Completion, UringFile};
+
+#[cfg(feature = "blocking")]
+extern crate blocking;
+#[cfg(feature = "bytemuck")]
+extern crate bytemuck;
+#[cfg(all(feature = "io_uring", target_os = "linux"))]
+extern crate io_uring;
+#[cfg(unix)]
+extern crate libc;
+#[cfg(feature = "tokio")]
+extern crate tokio;
 
+#[macro_use]
+mod macros;
+
+mod advice;
+mod append;
+mod async_ext;
+mod batch;
+mod buf;
+mod bytes;
+mod copy;
+mod cursor;
+mod direct;
+mod error;
+mod extents;
+mod flags;
+mod impls;
+mod lock;
+mod lock_manager;
+pub mod prelude;
+mod rmw;
+#[cfg(unix)]
+mod send;
+mod slice;
+mod space;
 mod sys;
+#[cfg(test)]
+mod test_util;
+#[cfg(all(feature = "io_uring", target_os = "linux"))]
+mod uring;
+
+// The size of the buffers used when data passes through userspace, such as
+// when copying data or writing zeros.
+const BUFFER_SIZE: usize = 64 * 1024;
+
+// Locks `mutex`, ignoring poisoning. The state guarded by the mutexes of this
+// crate is consistent wherever a panic can occur, so it remains usable.
+fn lock_ignoring_poison<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
 
-/// This trait provides the extension methods for reading and writing files at
-/// specified offsets.
+/// This trait provides the methods for reading at specified offsets.
 ///
 /// Note the difference between Windows and Unix behavior listed in the
 /// "Platform-specific behavior" sections.
-pub trait FileExt {
+pub trait ReadAt {
     /// Reads a number of bytes, starting at a given file offset.
     ///
     /// Returns the number of bytes read.
@@ -46,6 +143,260 @@ pub trait FileExt {
     /// whereas the Unix version does not.
     fn read_offset(&self, buf: &mut [u8], offset: u64) -> io::Result<usize>;
 
+    /// Returns the size of the underlying data in bytes.
+    ///
+    /// For files, this is the current length of the file. Reads starting at
+    /// or beyond this offset return `Ok(0)`.
+    fn size(&self) -> io::Result<u64>;
+
+    /// Like `read_offset`, but with per-call flags modifying the behavior of
+    /// the read.
+    ///
+    /// With `RwFlags::NOWAIT`, the read fails with `ErrorKind::WouldBlock`
+    /// instead of waiting for data that is not in the page cache. If the
+    /// flags are not supported, an error of kind `ErrorKind::Unsupported` is
+    /// returned.
+    ///
+    /// The default implementation calls `read_offset` if `flags` is empty
+    /// and fails with `ErrorKind::Unsupported` otherwise.
+    ///
+    /// # Platform-specific behavior
+    ///
+    /// For `File`, this function corresponds to the `preadv2` function on
+    /// Linux. On other platforms, only empty flags are supported.
+    fn read_offset_with_flags(
+        &self,
+        buf: &mut [u8],
+        offset: u64,
+        flags: RwFlags,
+    ) -> io::Result<usize> {
+        if !flags.is_empty() {
+            return Err(flags::unsupported());
+        }
+        self.read_offset(buf, offset)
+    }
+
+    /// Reads the exact number of bytes required to fill `buf`, starting at a
+    /// given file offset.
+    ///
+    /// This repeatedly calls `read_offset`, advancing the offset and the
+    /// buffer after short reads and retrying reads that fail with
+    /// `ErrorKind::Interrupted`. If the end of the file is reached before the
+    /// buffer is filled, an error of kind `ErrorKind::UnexpectedEof` is
+    /// returned.
+    ///
+    /// On error, the returned `TransferError` records how many bytes at the
+    /// start of `buf` were filled before the failure. The remaining contents
+    /// of `buf` are unspecified.
+    fn read_exact_offset(&self, mut buf: &mut [u8], mut offset: u64) -> Result<(), TransferError> {
+        let mut transferred = 0;
+        while !buf.is_empty() {
+            match self.read_offset(buf, offset) {
+                Ok(0) => break,
+                Ok(n) => {
+                    let tmp = buf;
+                    buf = &mut tmp[n..];
+                    offset += n as u64;
+                    transferred += n;
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(TransferError::new(transferred, e)),
+            }
+        }
+        if !buf.is_empty() {
+            return Err(TransferError::new(
+                transferred,
+                io::Error::new(io::ErrorKind::UnexpectedEof, "failed to fill whole buffer"),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Like `read_offset`, except that it reads into the unfilled part of a
+    /// `ReadBuf`, which may be uninitialized.
+    ///
+    /// The data read is appended to the filled part of the `ReadBuf` that
+    /// `buf` was created from. The number of bytes read can be determined
+    /// from the change in length of the filled part, or from
+    /// `ReadBufCursor::written`; if it does not change, the end of the file
+    /// has been reached or the buffer was already full.
+    ///
+    /// The default implementation initializes the unfilled part of `buf` and
+    /// calls `read_offset`.
+    ///
+    /// # Platform-specific behavior
+    ///
+    /// For `File`, this function reads directly into uninitialized memory
+    /// using the `pread` function on Unix. On Windows, the buffer is
+    /// initialized first.
+    fn read_buf_offset(&self, mut buf: ReadBufCursor, offset: u64) -> io::Result<()> {
+        let n = self.read_offset(buf.initialize_unfilled(), offset)?;
+        buf.advance(n);
+        Ok(())
+    }
+
+    /// Reads up to `len` bytes starting at a given file offset, appending
+    /// them to `vec`.
+    ///
+    /// The data is read directly into the spare capacity of `vec`, without
+    /// initializing it first. This repeatedly calls `read_buf_offset` until
+    /// `len` bytes have been read or the end of the file is reached,
+    /// retrying reads that fail with `ErrorKind::Interrupted`. Returns the
+    /// number of bytes appended.
+    ///
+    /// If an error occurs, the bytes read so far remain appended to `vec`.
+    fn read_to_vec_offset(
+        &self,
+        vec: &mut Vec<u8>,
+        mut offset: u64,
+        len: usize,
+    ) -> io::Result<usize> {
+        vec.reserve(len);
+        let mut read = 0;
+        while read < len {
+            let n = {
+                let mut buf = ReadBuf::uninit(&mut vec.spare_capacity_mut()[..len - read]);
+                match self.read_buf_offset(buf.unfilled(), offset) {
+                    Ok(()) => buf.filled().len(),
+                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => return Err(e),
+                }
+            };
+            if n == 0 {
+                break;
+            }
+            // `ReadBuf` guarantees that its filled part is initialized, and
+            // `read_buf_offset` can only fill it through the cursor, so it
+            // lies within the spare capacity.
+            unsafe {
+                vec.set_len(vec.len() + n);
+            }
+            read += n;
+            offset += n as u64;
+        }
+        Ok(read)
+    }
+
+    /// Like `read_offset`, except that it reads into a slice of buffers.
+    ///
+    /// Data is copied to fill each buffer in order, with the final buffer
+    /// written to possibly being only partially filled. Returns the total
+    /// number of bytes read.
+    ///
+    /// The default implementation calls `read_offset` with the first
+    /// non-empty buffer.
+    ///
+    /// # Platform-specific behavior
+    ///
+    /// For `File`, this function corresponds to the `preadv` function on
+    /// Linux, Android and the BSDs. On other platforms, only the first
+    /// non-empty buffer is read into.
+    fn read_vectored_offset(&self, bufs: &mut [IoSliceMut], offset: u64) -> io::Result<usize> {
+        let buf = bufs
+            .iter_mut()
+            .find(|b| !b.is_empty())
+            .map_or(&mut [][..], |b| &mut **b);
+        self.read_offset(buf, offset)
+    }
+
+    /// Reads the exact number of bytes required to fill `bufs`, starting at
+    /// a given file offset.
+    ///
+    /// This is the vectored equivalent of `read_exact_offset`. Short reads
+    /// may end in the middle of a buffer, in which case the next read
+    /// continues at the first unfilled byte. The slice of buffers is modified
+    /// in the process; its contents are unspecified after this function
+    /// returns.
+    ///
+    /// On error, the returned `TransferError` records how many bytes were
+    /// filled, counting from the start of the first buffer.
+    fn read_exact_vectored_offset(
+        &self,
+        mut bufs: &mut [IoSliceMut],
+        mut offset: u64,
+    ) -> Result<(), TransferError> {
+        let mut transferred = 0;
+        IoSliceMut::advance_slices(&mut bufs, 0);
+        while !bufs.is_empty() {
+            match self.read_vectored_offset(bufs, offset) {
+                Ok(0) => {
+                    return Err(TransferError::new(
+                        transferred,
+                        io::Error::new(io::ErrorKind::UnexpectedEof, "failed to fill whole buffer"),
+                    ));
+                }
+                Ok(n) => {
+                    IoSliceMut::advance_slices(&mut bufs, n);
+                    offset += n as u64;
+                    transferred += n;
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(TransferError::new(transferred, e)),
+            }
+        }
+        Ok(())
+    }
+
+    /// Performs a batch of reads, each into its buffer at its offset.
+    ///
+    /// Returns the result of each read, in the same order as `reqs`. Each
+    /// read behaves like `read_offset`, in particular it may be short. The
+    /// reads are independent of each other and may be performed in any order
+    /// or concurrently.
+    ///
+    /// The default implementation calls `read_offset` for each entry in
+    /// turn. `File` spreads batches with many entries and bytes across
+    /// multiple threads, and `UringFile` submits them to the kernel at once.
+    fn read_offsets_batch(&self, reqs: &mut [(u64, &mut [u8])]) -> Vec<io::Result<usize>> {
+        reqs.iter_mut()
+            .map(|&mut (offset, ref mut buf)| self.read_offset(buf, offset))
+            .collect()
+    }
+
+    /// Announces the access pattern for the range `[offset, offset + len)`.
+    ///
+    /// A `len` of zero extends the range to the end of the data. This is
+    /// only a hint, which implementations are free to ignore.
+    ///
+    /// The default implementation does nothing, which is appropriate for
+    /// data held in memory.
+    ///
+    /// # Platform-specific behavior
+    ///
+    /// For `File`, this corresponds to the `posix_fadvise` function on
+    /// Linux, Android and FreeBSD, and does nothing on other platforms.
+    fn advise_range(&self, offset: u64, len: u64, advice: Advice) -> io::Result<()> {
+        let _ = (offset, len, advice);
+        Ok(())
+    }
+
+    /// Starts reading the range `[offset, offset + len)` into the cache in
+    /// the background, so that subsequent reads do not have to wait.
+    ///
+    /// The default implementation does nothing, which is appropriate for
+    /// data held in memory.
+    ///
+    /// # Platform-specific behavior
+    ///
+    /// For `File`, this corresponds to the `readahead` function on Linux,
+    /// and to `advise_range` with `Advice::WillNeed` elsewhere.
+    fn readahead(&self, offset: u64, len: u64) -> io::Result<()> {
+        let _ = (offset, len);
+        Ok(())
+    }
+}
+
+/// This trait provides the methods for writing at specified offsets.
+///
+/// As writing only requires a shared reference, in-memory buffers need
+/// interior mutability to implement this trait. It is implemented for
+/// `RefCell<Vec<u8>>` and `Mutex<Vec<u8>>`, which grow like a file when
+/// written past their end, and likewise for `RefCell<Cursor<Vec<u8>>>` and
+/// `Mutex<Cursor<Vec<u8>>>`.
+///
+/// Note the difference between Windows and Unix behavior listed in the
+/// "Platform-specific behavior" sections.
+pub trait WriteAt {
     /// Writes a number of bytes, starting at a given file offset.
     ///
     /// Returns the number of bytes written.
@@ -67,15 +418,481 @@ pub trait FileExt {
     /// particular, the Windows version of this function moves the file cursor,
     /// whereas the Unix version does not.
     fn write_offset(&self, buf: &[u8], offset: u64) -> io::Result<usize>;
+
+    /// Truncates or extends the underlying data to the given size.
+    ///
+    /// If the data is extended, the new bytes read as zeros. For files, this
+    /// delegates to `File::set_len`.
+    fn set_size(&self, size: u64) -> io::Result<()>;
+
+    /// Like `write_offset`, but with per-call flags modifying the behavior of
+    /// the write.
+    ///
+    /// With `RwFlags::DSYNC`, the written data is durable once this function
+    /// returns, as if the file had been opened with `O_DSYNC`. If the flags
+    /// are not supported, an error of kind `ErrorKind::Unsupported` is
+    /// returned.
+    ///
+    /// The default implementation calls `write_offset` if `flags` is empty
+    /// and fails with `ErrorKind::Unsupported` otherwise.
+    ///
+    /// # Platform-specific behavior
+    ///
+    /// For `File`, this function corresponds to the `pwritev2` function on
+    /// Linux. On other platforms, only empty flags are supported.
+    fn write_offset_with_flags(
+        &self,
+        buf: &[u8],
+        offset: u64,
+        flags: RwFlags,
+    ) -> io::Result<usize> {
+        if !flags.is_empty() {
+            return Err(flags::unsupported());
+        }
+        self.write_offset(buf, offset)
+    }
+
+    /// Writes an entire buffer, starting at a given file offset.
+    ///
+    /// This repeatedly calls `write_offset`, advancing the offset and the
+    /// buffer after short writes and retrying writes that fail with
+    /// `ErrorKind::Interrupted`. If `write_offset` returns `Ok(0)` before the
+    /// whole buffer has been written, an error of kind
+    /// `ErrorKind::WriteZero` is returned.
+    ///
+    /// On error, the returned `TransferError` records how many bytes at the
+    /// start of `buf` were written before the failure.
+    fn write_all_offset(&self, mut buf: &[u8], mut offset: u64) -> Result<(), TransferError> {
+        let mut transferred = 0;
+        while !buf.is_empty() {
+            match self.write_offset(buf, offset) {
+                Ok(0) => {
+                    return Err(TransferError::new(
+                        transferred,
+                        io::Error::new(io::ErrorKind::WriteZero, "failed to write whole buffer"),
+                    ));
+                }
+                Ok(n) => {
+                    buf = &buf[n..];
+                    offset += n as u64;
+                    transferred += n;
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(TransferError::new(transferred, e)),
+            }
+        }
+        Ok(())
+    }
+
+    /// Like `write_offset`, except that it writes from a slice of buffers.
+    ///
+    /// Data is copied from each buffer in order, with the final buffer read
+    /// from possibly being only partially consumed. Returns the total number
+    /// of bytes written.
+    ///
+    /// The default implementation calls `write_offset` with the first
+    /// non-empty buffer.
+    ///
+    /// # Platform-specific behavior
+    ///
+    /// For `File`, this function corresponds to the `pwritev` function on
+    /// Linux, Android and the BSDs. On other platforms, only the first
+    /// non-empty buffer is written.
+    fn write_vectored_offset(&self, bufs: &[IoSlice], offset: u64) -> io::Result<usize> {
+        let buf = bufs
+            .iter()
+            .find(|b| !b.is_empty())
+            .map_or(&[][..], |b| &**b);
+        self.write_offset(buf, offset)
+    }
+
+    /// Writes all buffers in `bufs`, starting at a given file offset.
+    ///
+    /// This is the vectored equivalent of `write_all_offset`. Short writes
+    /// may end in the middle of a buffer, in which case the next write
+    /// continues at the first unwritten byte. The slice of buffers is
+    /// modified in the process; its contents are unspecified after this
+    /// function returns.
+    ///
+    /// On error, the returned `TransferError` records how many bytes were
+    /// written, counting from the start of the first buffer.
+    fn write_all_vectored_offset(
+        &self,
+        mut bufs: &mut [IoSlice],
+        mut offset: u64,
+    ) -> Result<(), TransferError> {
+        let mut transferred = 0;
+        IoSlice::advance_slices(&mut bufs, 0);
+        while !bufs.is_empty() {
+            match self.write_vectored_offset(bufs, offset) {
+                Ok(0) => {
+                    return Err(TransferError::new(
+                        transferred,
+                        io::Error::new(io::ErrorKind::WriteZero, "failed to write whole buffer"),
+                    ));
+                }
+                Ok(n) => {
+                    IoSlice::advance_slices(&mut bufs, n);
+                    offset += n as u64;
+                    transferred += n;
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(TransferError::new(transferred, e)),
+            }
+        }
+        Ok(())
+    }
+
+    /// Performs a batch of writes, each of its buffer at its offset.
+    ///
+    /// Returns the result of each write, in the same order as `reqs`. Each
+    /// write behaves like `write_offset`, in particular it may be short. The
+    /// writes are independent of each other and may be performed in any
+    /// order or concurrently, so overlapping writes leave the overlapping
+    /// range in an unspecified state.
+    ///
+    /// The default implementation calls `write_offset` for each entry in
+    /// turn. `File` spreads batches with many entries and bytes across
+    /// multiple threads, and `UringFile` submits them to the kernel at once.
+    fn write_offsets_batch(&self, reqs: &[(u64, &[u8])]) -> Vec<io::Result<usize>> {
+        reqs.iter()
+            .map(|&(offset, buf)| self.write_offset(buf, offset))
+            .collect()
+    }
+
+    /// Allocates storage for the range `[offset, offset + len)`.
+    ///
+    /// After a successful call, writes into the range will not fail due to
+    /// lack of space. If the range extends past the end of the data, its
+    /// size is increased accordingly, the new bytes reading as zeros.
+    ///
+    /// The default implementation fails with `ErrorKind::Unsupported`.
+    ///
+    /// # Platform-specific behavior
+    ///
+    /// For `File`, this corresponds to the `fallocate` function with a mode
+    /// of zero on Linux. Where that is not supported, the file is merely
+    /// extended using `set_size` if necessary, without reserving storage.
+    fn allocate_range(&self, offset: u64, len: u64) -> io::Result<()> {
+        let _ = (offset, len);
+        Err(space::unsupported("allocating ranges"))
+    }
+
+    /// Deallocates the storage of the range `[offset, offset + len)`,
+    /// leaving a hole that reads as zeros.
+    ///
+    /// The size of the data is not changed, even if the range extends past
+    /// its end.
+    ///
+    /// The default implementation fails with `ErrorKind::Unsupported`.
+    ///
+    /// # Platform-specific behavior
+    ///
+    /// For `File`, this corresponds to the `fallocate` function with
+    /// `FALLOC_FL_PUNCH_HOLE` on Linux. Where that is not supported, the
+    /// part of the range within the file is overwritten with zeros, which
+    /// does not free any storage.
+    fn punch_hole(&self, offset: u64, len: u64) -> io::Result<()> {
+        let _ = (offset, len);
+        Err(space::unsupported("punching holes"))
+    }
+
+    /// Sets the range `[offset, offset + len)` to zeros.
+    ///
+    /// If the range extends past the end of the data, its size is increased
+    /// accordingly. Unlike `punch_hole`, storage for the range stays
+    /// allocated.
+    ///
+    /// The default implementation writes zeros using `write_all_offset`.
+    ///
+    /// # Platform-specific behavior
+    ///
+    /// For `File`, this corresponds to the `fallocate` function with
+    /// `FALLOC_FL_ZERO_RANGE` on Linux, which avoids writing the zeros on
+    /// supporting file systems. Elsewhere, zeros are written.
+    fn zero_range(&self, offset: u64, len: u64) -> io::Result<()> {
+        space::checked_end(offset, len)?;
+        space::write_zeros(self, offset, len)
+    }
+
+    /// Removes the range `[offset, offset + len)`, shifting the data after
+    /// it towards the start and reducing the size by `len`.
+    ///
+    /// File systems typically require the range to be aligned to their
+    /// block size and fail with `ErrorKind::InvalidInput` otherwise. The
+    /// range must not extend to or past the end of the data.
+    ///
+    /// The default implementation fails with `ErrorKind::Unsupported`.
+    ///
+    /// # Platform-specific behavior
+    ///
+    /// For `File`, this corresponds to the `fallocate` function with
+    /// `FALLOC_FL_COLLAPSE_RANGE` on Linux, and is unsupported elsewhere.
+    fn collapse_range(&self, offset: u64, len: u64) -> io::Result<()> {
+        let _ = (offset, len);
+        Err(space::unsupported("collapsing ranges"))
+    }
+
+    /// Inserts a hole of `len` bytes at `offset`, shifting the data after it
+    /// towards the end and increasing the size by `len`.
+    ///
+    /// File systems typically require the range to be aligned to their
+    /// block size and fail with `ErrorKind::InvalidInput` otherwise.
+    /// `offset` must lie before the end of the data.
+    ///
+    /// The default implementation fails with `ErrorKind::Unsupported`.
+    ///
+    /// # Platform-specific behavior
+    ///
+    /// For `File`, this corresponds to the `fallocate` function with
+    /// `FALLOC_FL_INSERT_RANGE` on Linux, and is unsupported elsewhere.
+    fn insert_range(&self, offset: u64, len: u64) -> io::Result<()> {
+        let _ = (offset, len);
+        Err(space::unsupported("inserting ranges"))
+    }
 }
 
-impl FileExt for File {
+/// This trait combines `ReadAt` and `WriteAt` for types that support both
+/// reading and writing at specified offsets.
+///
+/// It is implemented automatically for all types implementing both traits.
+/// Importing it does not make the methods of `ReadAt` and `WriteAt`
+/// callable; import those traits or the `prelude` as well.
+pub trait FileExt: ReadAt + WriteAt {}
+
+impl<T: ReadAt + WriteAt + ?Sized> FileExt for T {}
+
+impl ReadAt for File {
     #[inline]
     fn read_offset(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
         sys::read_offset(self, buf, offset)
     }
+    #[inline]
+    fn size(&self) -> io::Result<u64> {
+        self.metadata().map(|m| m.len())
+    }
+    #[inline]
+    fn read_buf_offset(&self, buf: ReadBufCursor, offset: u64) -> io::Result<()> {
+        sys::read_buf_offset(self, buf, offset)
+    }
+    #[inline]
+    fn read_vectored_offset(&self, bufs: &mut [IoSliceMut], offset: u64) -> io::Result<usize> {
+        sys::read_vectored_offset(self, bufs, offset)
+    }
+    #[inline]
+    fn read_offset_with_flags(
+        &self,
+        buf: &mut [u8],
+        offset: u64,
+        flags: RwFlags,
+    ) -> io::Result<usize> {
+        sys::read_offset_with_flags(self, buf, offset, flags)
+    }
+    fn read_offsets_batch(&self, reqs: &mut [(u64, &mut [u8])]) -> Vec<io::Result<usize>> {
+        batch::read_parallel(reqs, |buf, offset| sys::read_offset(self, buf, offset))
+    }
+    fn advise_range(&self, offset: u64, len: u64, advice: Advice) -> io::Result<()> {
+        sys::advise(self, offset, len, advice)
+    }
+    fn readahead(&self, offset: u64, len: u64) -> io::Result<()> {
+        sys::readahead(self, offset, len)
+    }
+}
+
+impl WriteAt for File {
     #[inline]
     fn write_offset(&self, buf: &[u8], offset: u64) -> io::Result<usize> {
         sys::write_offset(self, buf, offset)
     }
+    #[inline]
+    fn set_size(&self, size: u64) -> io::Result<()> {
+        self.set_len(size)
+    }
+    #[inline]
+    fn write_vectored_offset(&self, bufs: &[IoSlice], offset: u64) -> io::Result<usize> {
+        sys::write_vectored_offset(self, bufs, offset)
+    }
+    #[inline]
+    fn write_offset_with_flags(
+        &self,
+        buf: &[u8],
+        offset: u64,
+        flags: RwFlags,
+    ) -> io::Result<usize> {
+        sys::write_offset_with_flags(self, buf, offset, flags)
+    }
+    fn write_offsets_batch(&self, reqs: &[(u64, &[u8])]) -> Vec<io::Result<usize>> {
+        batch::write_parallel(reqs, |buf, offset| sys::write_offset(self, buf, offset))
+    }
+    fn allocate_range(&self, offset: u64, len: u64) -> io::Result<()> {
+        match sys::fallocate(self, sys::Fallocate::Allocate, offset, len) {
+            Err(ref e) if e.kind() == io::ErrorKind::Unsupported => {
+                let end = space::checked_end(offset, len)?;
+                if end > self.size()? {
+                    self.set_len(end)?;
+                }
+                Ok(())
+            }
+            result => result,
+        }
+    }
+    fn punch_hole(&self, offset: u64, len: u64) -> io::Result<()> {
+        match sys::fallocate(self, sys::Fallocate::PunchHole, offset, len) {
+            Err(ref e) if e.kind() == io::ErrorKind::Unsupported => {
+                let end = cmp::min(space::checked_end(offset, len)?, self.size()?);
+                if end > offset {
+                    space::write_zeros(self, offset, end - offset)?;
+                }
+                Ok(())
+            }
+            result => result,
+        }
+    }
+    fn zero_range(&self, offset: u64, len: u64) -> io::Result<()> {
+        match sys::fallocate(self, sys::Fallocate::ZeroRange, offset, len) {
+            Err(ref e) if e.kind() == io::ErrorKind::Unsupported => {
+                space::checked_end(offset, len)?;
+                space::write_zeros(self, offset, len)
+            }
+            result => result,
+        }
+    }
+    fn collapse_range(&self, offset: u64, len: u64) -> io::Result<()> {
+        sys::fallocate(self, sys::Fallocate::CollapseRange, offset, len)
+    }
+    fn insert_range(&self, offset: u64, len: u64) -> io::Result<()> {
+        sys::fallocate(self, sys::Fallocate::InsertRange, offset, len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::{Cell, RefCell};
+    use std::cmp;
+    use std::io;
+    use std::io::{IoSlice, IoSliceMut};
+
+    use {ReadAt, WriteAt};
+
+    // Data transferring at most `limit` bytes per call, and failing every
+    // other call with `ErrorKind::Interrupted` if `interrupt` is set.
+    struct Choppy {
+        data: RefCell<Vec<u8>>,
+        limit: usize,
+        interrupt: bool,
+        calls: Cell<usize>,
+    }
+
+    impl Choppy {
+        fn new(data: &[u8], limit: usize, interrupt: bool) -> Choppy {
+            Choppy {
+                data: RefCell::new(data.to_vec()),
+                limit,
+                interrupt,
+                calls: Cell::new(0),
+            }
+        }
+
+        fn call(&self) -> io::Result<()> {
+            self.calls.set(self.calls.get() + 1);
+            if self.interrupt && self.calls.get() % 2 == 1 {
+                return Err(io::Error::from(io::ErrorKind::Interrupted));
+            }
+            Ok(())
+        }
+    }
+
+    impl ReadAt for Choppy {
+        fn read_offset(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+            self.call()?;
+            let len = cmp::min(buf.len(), self.limit);
+            self.data.read_offset(&mut buf[..len], offset)
+        }
+        fn size(&self) -> io::Result<u64> {
+            self.data.size()
+        }
+    }
+
+    impl WriteAt for Choppy {
+        fn write_offset(&self, buf: &[u8], offset: u64) -> io::Result<usize> {
+            self.call()?;
+            let len = cmp::min(buf.len(), self.limit);
+            self.data.write_offset(&buf[..len], offset)
+        }
+        fn set_size(&self, size: u64) -> io::Result<()> {
+            self.data.set_size(size)
+        }
+    }
+
+    #[test]
+    fn read_exact_vectored_short_and_interrupted() {
+        for &interrupt in &[false, true] {
+            let data = Choppy::new(b"0123456789", 3, interrupt);
+            let (mut a, mut b, mut c) = ([0; 2], [0; 0], [0; 5]);
+            let mut bufs = [
+                IoSliceMut::new(&mut a),
+                IoSliceMut::new(&mut b),
+                IoSliceMut::new(&mut c),
+            ];
+            data.read_exact_vectored_offset(&mut bufs, 1).unwrap();
+            assert_eq!(&a, b"12");
+            assert_eq!(&c, b"34567");
+        }
+    }
+
+    #[test]
+    fn read_exact_vectored_eof() {
+        let data = Choppy::new(b"0123456789", 3, true);
+        let (mut a, mut b) = ([0; 4], [0; 4]);
+        let mut bufs = [IoSliceMut::new(&mut a), IoSliceMut::new(&mut b)];
+        let err = data.read_exact_vectored_offset(&mut bufs, 4).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(err.transferred(), 6);
+        assert_eq!(&a, b"4567");
+        assert_eq!(&b[..2], b"89");
+    }
+
+    #[test]
+    fn read_exact_short_and_interrupted() {
+        let data = Choppy::new(b"0123456789", 4, true);
+        let mut buf = [0; 9];
+        data.read_exact_offset(&mut buf, 1).unwrap();
+        assert_eq!(&buf, b"123456789");
+        let err = data.read_exact_offset(&mut buf, 3).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(err.transferred(), 7);
+    }
+
+    #[test]
+    fn write_all_vectored_short_and_interrupted() {
+        for &interrupt in &[false, true] {
+            let data = Choppy::new(b"", 3, interrupt);
+            let mut bufs = [
+                IoSlice::new(b"ab"),
+                IoSlice::new(b""),
+                IoSlice::new(b"cdefg"),
+            ];
+            data.write_all_vectored_offset(&mut bufs, 2).unwrap();
+            assert_eq!(*data.data.borrow(), b"\0\0abcdefg");
+        }
+    }
+
+    #[test]
+    fn write_all_short_and_interrupted() {
+        let data = Choppy::new(b"", 2, true);
+        data.write_all_offset(b"hello", 1).unwrap();
+        assert_eq!(*data.data.borrow(), b"\0hello");
+    }
+
+    #[test]
+    fn write_zero() {
+        let data = Choppy::new(b"", 0, false);
+        let err = data.write_all_offset(b"abc", 0).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WriteZero);
+        assert_eq!(err.transferred(), 0);
+        let data = Choppy::new(b"", 0, true);
+        let mut bufs = [IoSlice::new(b""), IoSlice::new(b"abc")];
+        let err = data.write_all_vectored_offset(&mut bufs, 0).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WriteZero);
+    }
 }
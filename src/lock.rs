@@ -0,0 +1,174 @@
+use std::fs::File;
+use std::io;
+
+use sys;
+
+/// The kind of a byte-range lock.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum LockKind {
+    /// A shared (read) lock. Several shared locks on the same range can be
+    /// held at once, but no exclusive lock.
+    Shared,
+    /// An exclusive (write) lock, which conflicts with all other locks on
+    /// the same range.
+    Exclusive,
+}
+
+/// A lock on a byte range of a file, created by `lock_range` or
+/// `try_lock_range`.
+///
+/// The lock is released when this guard is dropped.
+#[derive(Debug)]
+#[must_use = "the lock is released immediately if the guard is not used"]
+pub struct RangeLock<'a> {
+    file: &'a File,
+    offset: u64,
+    len: u64,
+    kind: LockKind,
+}
+
+impl<'a> RangeLock<'a> {
+    /// Returns the file the lock is held on.
+    pub fn file(&self) -> &'a File {
+        self.file
+    }
+
+    /// Returns the kind of the lock.
+    pub fn kind(&self) -> LockKind {
+        self.kind
+    }
+}
+
+impl<'a> Drop for RangeLock<'a> {
+    fn drop(&mut self) {
+        let _ = sys::lock_range(self.file, self.offset, self.len, None, true);
+    }
+}
+
+/// Locks the range `[offset, offset + len)` of `file`, waiting until
+/// conflicting locks held by others are released.
+///
+/// A `len` of zero locks everything from `offset` on, including data
+/// appended later. The lock is advisory: it only excludes other lockers,
+/// not reads or writes. Shared locks require `file` to be open for reading
+/// and exclusive locks require it to be open for writing.
+///
+/// Locking a range overlapping one already locked through the same handle
+/// does not conflict, but replaces the lock on the overlap, and unlocking
+/// either releases the overlap.
+///
+/// # Platform-specific behavior
+///
+/// On Linux, this uses open file description locks (`F_OFD_SETLKW`). They
+/// belong to the file handle and its duplicates, so locks taken through
+/// separately opened handles conflict, even within a process.
+///
+/// Other Unix platforms and Linux kernels before 3.15 use classic POSIX
+/// record locks (`F_SETLKW`) instead. These belong to the process, which
+/// has several caveats: locks taken through different handles within the
+/// same process never conflict, and closing any handle to the file releases
+/// all of the process's locks on it.
+///
+/// Fails with `ErrorKind::Unsupported` on other platforms.
+///
+/// ```no_run
+/// use file_offset::{lock_range, LockKind, WriteAt};
+/// use std::fs::OpenOptions;
+///
+/// let file = OpenOptions::new().write(true).open("records.db").unwrap();
+/// let _lock = lock_range(&file, 4096, 512, LockKind::Exclusive).unwrap();
+/// file.write_all_offset(&[0; 512], 4096).unwrap();
+/// ```
+pub fn lock_range(file: &File, offset: u64, len: u64, kind: LockKind) -> io::Result<RangeLock<'_>> {
+    sys::lock_range(file, offset, len, Some(kind), true)?;
+    Ok(RangeLock {
+        file,
+        offset,
+        len,
+        kind,
+    })
+}
+
+/// Like `lock_range`, but fails with an error of kind
+/// `ErrorKind::WouldBlock` instead of waiting if a conflicting lock is held.
+pub fn try_lock_range(
+    file: &File,
+    offset: u64,
+    len: u64,
+    kind: LockKind,
+) -> io::Result<RangeLock<'_>> {
+    sys::lock_range(file, offset, len, Some(kind), false)?;
+    Ok(RangeLock {
+        file,
+        offset,
+        len,
+        kind,
+    })
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use std::fs::{File, OpenOptions};
+    use std::io;
+    use std::os::unix::io::AsRawFd;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    use super::{lock_range, try_lock_range, LockKind, RangeLock};
+    use test_util::temp_file;
+
+    // Opens a second handle to `file`, whose locks conflict with those of
+    // `file`.
+    fn reopen(file: &File) -> File {
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(format!("/proc/self/fd/{}", file.as_raw_fd()))
+            .unwrap()
+    }
+
+    fn assert_would_block(result: io::Result<RangeLock>) {
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::WouldBlock);
+    }
+
+    #[test]
+    fn separate_handles_conflict() {
+        let a = temp_file(&[0; 100]);
+        let b = reopen(&a);
+        let lock = try_lock_range(&a, 10, 20, LockKind::Exclusive).unwrap();
+        assert_eq!(lock.kind(), LockKind::Exclusive);
+        assert_would_block(try_lock_range(&b, 25, 10, LockKind::Shared));
+        assert_would_block(try_lock_range(&b, 0, 0, LockKind::Exclusive));
+        let _other = try_lock_range(&b, 30, 10, LockKind::Exclusive).unwrap();
+        // Locks through the same handle do not conflict.
+        let _same = try_lock_range(&a, 15, 5, LockKind::Shared).unwrap();
+
+        let c = reopen(&a);
+        let _c = try_lock_range(&c, 50, 10, LockKind::Shared).unwrap();
+        let _b = try_lock_range(&b, 55, 10, LockKind::Shared).unwrap();
+        assert_would_block(try_lock_range(&a, 58, 1, LockKind::Exclusive));
+    }
+
+    #[test]
+    fn drop_releases_lock() {
+        let a = temp_file(&[0; 100]);
+        let b = reopen(&a);
+        let lock = try_lock_range(&a, 0, 0, LockKind::Exclusive).unwrap();
+        assert_would_block(try_lock_range(&b, 99, 1, LockKind::Shared));
+        drop(lock);
+        drop(try_lock_range(&b, 0, 0, LockKind::Exclusive).unwrap());
+
+        let lock = lock_range(&a, 0, 10, LockKind::Exclusive).unwrap();
+        let (tx, rx) = mpsc::channel();
+        thread::scope(|s| {
+            s.spawn(|| {
+                let _lock = lock_range(&b, 5, 10, LockKind::Exclusive).unwrap();
+                tx.send(()).unwrap();
+            });
+            assert!(rx.recv_timeout(Duration::from_millis(50)).is_err());
+            drop(lock);
+            rx.recv().unwrap();
+        });
+    }
+}
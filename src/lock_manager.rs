@@ -0,0 +1,403 @@
+use std::cmp;
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use {lock_ignoring_poison, LockKind};
+
+/// A manager granting shared and exclusive locks on byte ranges within a
+/// process.
+///
+/// This coordinates threads performing read-modify-write cycles using
+/// positioned I/O on overlapping regions of the same data. Unlike
+/// `lock_range`, it works with any `ReadAt` and `WriteAt` implementor, but
+/// does not coordinate with other processes. The locks are advisory: they
+/// only exclude other users of the same manager.
+///
+/// Requests are granted in the order they were made: a request waits for
+/// earlier requests that conflict with it, even if those are still waiting
+/// themselves, so that exclusive requests are not starved by a stream of
+/// shared ones.
+///
+/// To hold locks on several ranges at once, acquire them with a single call
+/// to `lock_many`. Acquiring them one by one through separate calls can
+/// deadlock if other threads do so in a different order.
+///
+/// ```
+/// use file_offset::{LockKind, RangeLockManager, ReadAt, WriteAt};
+/// use std::sync::Mutex;
+///
+/// let data = Mutex::new(vec![0; 64]);
+/// let locks = RangeLockManager::new();
+///
+/// let _guard = locks.lock(8, 8, LockKind::Exclusive);
+/// let mut buf = [0; 8];
+/// data.read_exact_offset(&mut buf, 8).unwrap();
+/// buf[0] += 1;
+/// data.write_all_offset(&buf, 8).unwrap();
+/// ```
+#[derive(Debug, Default)]
+pub struct RangeLockManager {
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    // The granted locks, keyed by their start and id, mapping to their end
+    // and kind.
+    granted: BTreeMap<(u64, u64), (u64, LockKind)>,
+    // The lengths of the granted locks, mapping to the number of locks of
+    // each length. The longest one bounds how far before a range
+    // overlapping locks can start.
+    lens: BTreeMap<u64, usize>,
+    // The waiting requests, in the order they were made.
+    waiting: Vec<Request>,
+    next_id: u64,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Request {
+    id: u64,
+    start: u64,
+    end: u64,
+    kind: LockKind,
+}
+
+fn conflicts(a: LockKind, b: LockKind) -> bool {
+    a == LockKind::Exclusive || b == LockKind::Exclusive
+}
+
+impl State {
+    fn max_len(&self) -> u64 {
+        self.lens.keys().next_back().map_or(0, |&len| len)
+    }
+
+    fn conflicts_granted(&self, req: &Request) -> bool {
+        let from = (req.start.saturating_sub(self.max_len()), 0);
+        self.granted
+            .range(from..(req.end, 0))
+            .any(|(_, &(end, kind))| end > req.start && conflicts(kind, req.kind))
+    }
+
+    fn conflicts_waiting(&self, req: &Request) -> bool {
+        self.waiting
+            .iter()
+            .take_while(|w| w.id < req.id)
+            .any(|w| w.start < req.end && req.start < w.end && conflicts(w.kind, req.kind))
+    }
+
+    // Requests made while already holding locks of the same acquisition do
+    // not queue behind earlier waiters. These could be waiting for the held
+    // locks, which would deadlock.
+    fn can_grant(&self, req: &Request, holding: bool) -> bool {
+        !self.conflicts_granted(req) && (holding || !self.conflicts_waiting(req))
+    }
+
+    fn grant(&mut self, req: &Request) -> (u64, u64) {
+        self.granted
+            .insert((req.start, req.id), (req.end, req.kind));
+        *self.lens.entry(req.end - req.start).or_insert(0) += 1;
+        (req.start, req.id)
+    }
+
+    fn release(&mut self, keys: &[(u64, u64)]) {
+        for key in keys {
+            let len = match self.granted.remove(key) {
+                Some((end, _)) => end - key.0,
+                None => continue,
+            };
+            if let Entry::Occupied(mut count) = self.lens.entry(len) {
+                *count.get_mut() -= 1;
+                if *count.get() == 0 {
+                    count.remove();
+                }
+            }
+        }
+    }
+
+    fn remove_waiting(&mut self, id: u64) {
+        if let Some(i) = self.waiting.iter().position(|w| w.id == id) {
+            self.waiting.remove(i);
+        }
+    }
+}
+
+/// A guard holding locks granted by a `RangeLockManager`.
+///
+/// The locks are released when the guard is dropped.
+#[derive(Debug)]
+#[must_use = "the locks are released immediately if the guard is not used"]
+pub struct RangeGuard<'a> {
+    manager: &'a RangeLockManager,
+    keys: Vec<(u64, u64)>,
+}
+
+impl<'a> Drop for RangeGuard<'a> {
+    fn drop(&mut self) {
+        if self.keys.is_empty() {
+            return;
+        }
+        self.manager.state().release(&self.keys);
+        self.manager.changed.notify_all();
+    }
+}
+
+impl RangeLockManager {
+    /// Creates a manager without any locks.
+    pub fn new() -> RangeLockManager {
+        RangeLockManager::default()
+    }
+
+    /// Locks the range `[offset, offset + len)`, waiting until conflicting
+    /// locks are released.
+    ///
+    /// Ranges extending past `u64::MAX` are clamped. Empty ranges never
+    /// conflict.
+    pub fn lock(&self, offset: u64, len: u64, kind: LockKind) -> RangeGuard<'_> {
+        self.lock_many(&[(offset, len, kind)])
+    }
+
+    /// Like `lock`, but returns `None` instead of waiting if the range
+    /// cannot be locked immediately.
+    pub fn try_lock(&self, offset: u64, len: u64, kind: LockKind) -> Option<RangeGuard<'_>> {
+        self.try_lock_many(&[(offset, len, kind)])
+    }
+
+    /// Like `lock`, but returns `None` if the range could not be locked
+    /// within `timeout`.
+    pub fn lock_timeout(
+        &self,
+        offset: u64,
+        len: u64,
+        kind: LockKind,
+        timeout: Duration,
+    ) -> Option<RangeGuard<'_>> {
+        self.lock_many_timeout(&[(offset, len, kind)], timeout)
+    }
+
+    /// Locks several ranges at once, each given as an offset, length and
+    /// kind, waiting until conflicting locks are released.
+    ///
+    /// Overlapping ranges are merged, the merged range being locked
+    /// exclusively if any of them is. The ranges are acquired in order of
+    /// their offsets, which prevents deadlocks between threads locking
+    /// overlapping sets of ranges.
+    pub fn lock_many(&self, ranges: &[(u64, u64, LockKind)]) -> RangeGuard<'_> {
+        self.acquire(ranges, Wait::Forever)
+            .expect("waiting without a timeout cannot fail")
+    }
+
+    /// Like `lock_many`, but returns `None` instead of waiting if any of the
+    /// ranges cannot be locked immediately.
+    pub fn try_lock_many(&self, ranges: &[(u64, u64, LockKind)]) -> Option<RangeGuard<'_>> {
+        self.acquire(ranges, Wait::Never)
+    }
+
+    /// Like `lock_many`, but returns `None` if the ranges could not all be
+    /// locked within `timeout`.
+    pub fn lock_many_timeout(
+        &self,
+        ranges: &[(u64, u64, LockKind)],
+        timeout: Duration,
+    ) -> Option<RangeGuard<'_>> {
+        let wait = match Instant::now().checked_add(timeout) {
+            Some(deadline) => Wait::Until(deadline),
+            None => Wait::Forever,
+        };
+        self.acquire(ranges, wait)
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        lock_ignoring_poison(&self.state)
+    }
+
+    fn acquire(&self, ranges: &[(u64, u64, LockKind)], wait: Wait) -> Option<RangeGuard<'_>> {
+        let ranges = merge(ranges);
+        let mut guard = RangeGuard {
+            manager: self,
+            keys: Vec::with_capacity(ranges.len()),
+        };
+        let mut state = self.state();
+        for &(start, end, kind) in &ranges {
+            let req = Request {
+                id: state.next_id,
+                start,
+                end,
+                kind,
+            };
+            state.next_id += 1;
+            let holding = !guard.keys.is_empty();
+            if state.can_grant(&req, holding) {
+                guard.keys.push(state.grant(&req));
+                continue;
+            }
+            if let Wait::Never = wait {
+                state.release(&guard.keys);
+                guard.keys.clear();
+                drop(state);
+                self.changed.notify_all();
+                return None;
+            }
+
+            state.waiting.push(req);
+            loop {
+                state = match wait {
+                    Wait::Until(deadline) => {
+                        let now = Instant::now();
+                        if now >= deadline {
+                            state.remove_waiting(req.id);
+                            state.release(&guard.keys);
+                            guard.keys.clear();
+                            drop(state);
+                            self.changed.notify_all();
+                            return None;
+                        }
+                        self.changed
+                            .wait_timeout(state, deadline - now)
+                            .unwrap_or_else(|e| e.into_inner())
+                            .0
+                    }
+                    _ => self.changed.wait(state).unwrap_or_else(|e| e.into_inner()),
+                };
+                if state.can_grant(&req, holding) {
+                    state.remove_waiting(req.id);
+                    guard.keys.push(state.grant(&req));
+                    break;
+                }
+            }
+            // Removing this request may allow later ones to proceed.
+            self.changed.notify_all();
+        }
+        drop(state);
+        Some(guard)
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Wait {
+    Never,
+    Until(Instant),
+    Forever,
+}
+
+// Converts the ranges to sorted, non-overlapping `(start, end, kind)`
+// triples, dropping empty ones.
+fn merge(ranges: &[(u64, u64, LockKind)]) -> Vec<(u64, u64, LockKind)> {
+    let mut sorted: Vec<_> = ranges
+        .iter()
+        .filter(|&&(_, len, _)| len != 0)
+        .map(|&(offset, len, kind)| (offset, offset.saturating_add(len), kind))
+        .collect();
+    sorted.sort_by_key(|&(start, _, _)| start);
+    let mut merged: Vec<(u64, u64, LockKind)> = Vec::with_capacity(sorted.len());
+    for (start, end, kind) in sorted {
+        match merged.last_mut() {
+            Some(last) if start < last.1 => {
+                last.1 = cmp::max(last.1, end);
+                if kind == LockKind::Exclusive {
+                    last.2 = LockKind::Exclusive;
+                }
+            }
+            _ => merged.push((start, end, kind)),
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Barrier};
+    use std::thread;
+    use std::time::Duration;
+
+    use super::RangeLockManager;
+    use LockKind::{Exclusive, Shared};
+
+    // Waits until `count` requests are waiting for locks.
+    fn wait_for_waiters(locks: &RangeLockManager, count: usize) {
+        while locks.state().waiting.len() < count {
+            thread::yield_now();
+        }
+    }
+
+    #[test]
+    fn shared_and_exclusive() {
+        let locks = RangeLockManager::new();
+        let a = locks.lock(0, 10, Shared);
+        let b = locks.try_lock(5, 10, Shared).unwrap();
+        assert!(locks.try_lock(9, 1, Exclusive).is_none());
+        assert!(locks.try_lock(10, 5, Exclusive).is_none());
+        assert!(locks.try_lock(15, 5, Exclusive).is_some());
+        drop(a);
+        assert!(locks.try_lock(0, 5, Exclusive).is_some());
+        assert!(locks.try_lock(0, 6, Exclusive).is_none());
+        drop(b);
+        let c = locks.try_lock(0, 20, Exclusive).unwrap();
+        assert!(locks.try_lock(19, 1, Shared).is_none());
+        assert!(locks.try_lock(3, 0, Exclusive).is_some());
+        drop(c);
+        assert!(locks.state().granted.is_empty());
+    }
+
+    #[test]
+    fn release_shrinks_scan() {
+        let locks = RangeLockManager::new();
+        let long = locks.lock(0, 1 << 40, Shared);
+        let short = locks.lock(1 << 41, 10, Shared);
+        let other = locks.lock(1 << 42, 10, Shared);
+        assert_eq!(locks.state().max_len(), 1 << 40);
+        drop(long);
+        assert_eq!(locks.state().max_len(), 10);
+        drop(short);
+        assert_eq!(locks.state().max_len(), 10);
+        drop(other);
+        assert_eq!(locks.state().max_len(), 0);
+    }
+
+    #[test]
+    fn fifo_fairness() {
+        let locks = RangeLockManager::new();
+        let shared = locks.lock(0, 10, Shared);
+        thread::scope(|s| {
+            let writer = s.spawn(|| {
+                let _guard = locks.lock(5, 10, Exclusive);
+            });
+            wait_for_waiters(&locks, 1);
+            // A later shared request queues behind the exclusive one, even
+            // though it is compatible with the granted lock.
+            assert!(locks.try_lock(0, 10, Shared).is_none());
+            assert!(locks
+                .lock_timeout(8, 1, Shared, Duration::from_millis(10))
+                .is_none());
+            // Requests not conflicting with the waiter proceed.
+            assert!(locks.try_lock(0, 5, Shared).is_some());
+            drop(shared);
+            writer.join().unwrap();
+        });
+        assert!(locks.try_lock(0, 10, Shared).is_some());
+    }
+
+    #[test]
+    fn lock_many_does_not_deadlock() {
+        const THREADS: usize = 4;
+        let locks = RangeLockManager::new();
+        let barrier = Arc::new(Barrier::new(THREADS));
+        thread::scope(|s| {
+            for i in 0..THREADS {
+                let (locks, barrier) = (&locks, barrier.clone());
+                s.spawn(move || {
+                    let mut ranges = [(0, 4, Exclusive), (8, 4, Shared), (16, 4, Exclusive)];
+                    ranges.rotate_left(i % 3);
+                    barrier.wait();
+                    for _ in 0..1000 {
+                        let _guard = locks.lock_many(&ranges);
+                    }
+                });
+            }
+        });
+        let state = locks.state();
+        assert!(state.granted.is_empty() && state.waiting.is_empty());
+    }
+}
@@ -0,0 +1,132 @@
+// Defines a set of flags stored as the bits of a `u32`, with the methods and
+// operators shared by the flag types of this crate. `$platform` names the
+// platform constants the flags correspond to, for the documentation.
+macro_rules! flags {
+    (
+        $(#[$attr:meta])*
+        pub struct $name:ident: $platform:expr;
+        $(
+            $(#[$flag_attr:meta])*
+            const $flag:ident = $bit:expr;
+        )*
+    ) => {
+        $(#[$attr])*
+        #[derive(Clone, Copy, PartialEq, Eq, Hash, Default)]
+        pub struct $name(u32);
+
+        impl $name {
+            $(
+                $(#[$flag_attr])*
+                pub const $flag: $name = $name(1 << $bit);
+            )*
+
+            const ALL: u32 = 0 $(| 1 << $bit)*;
+
+            /// Returns an empty set of flags.
+            pub const fn empty() -> $name {
+                $name(0)
+            }
+
+            /// Returns `true` if no flags are set.
+            pub const fn is_empty(self) -> bool {
+                self.0 == 0
+            }
+
+            /// Returns `true` if all flags in `other` are also set in `self`.
+            pub const fn contains(self, other: $name) -> bool {
+                self.0 & other.0 == other.0
+            }
+
+            /// Returns the flags as a raw bit pattern.
+            ///
+            #[doc = concat!(
+                "The bit pattern is specific to this crate and does not correspond to\n",
+                "the platform's `", $platform, "` constants."
+            )]
+            pub const fn bits(self) -> u32 {
+                self.0
+            }
+
+            /// Creates flags from a raw bit pattern, returning `None` if it
+            /// contains unknown bits.
+            pub const fn from_bits(bits: u32) -> Option<$name> {
+                if bits & !$name::ALL == 0 {
+                    Some($name(bits))
+                } else {
+                    None
+                }
+            }
+        }
+
+        impl ::std::ops::BitOr for $name {
+            type Output = $name;
+            fn bitor(self, other: $name) -> $name {
+                $name(self.0 | other.0)
+            }
+        }
+
+        impl ::std::ops::BitOrAssign for $name {
+            fn bitor_assign(&mut self, other: $name) {
+                self.0 |= other.0;
+            }
+        }
+
+        impl ::std::ops::BitAnd for $name {
+            type Output = $name;
+            fn bitand(self, other: $name) -> $name {
+                $name(self.0 & other.0)
+            }
+        }
+
+        impl ::std::fmt::Debug for $name {
+            fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+                let names: &[($name, &str)] = &[$(($name::$flag, stringify!($flag))),*];
+                f.write_str(concat!(stringify!($name), "("))?;
+                let mut first = true;
+                for &(flag, name) in names {
+                    if self.contains(flag) {
+                        if !first {
+                            f.write_str(" | ")?;
+                        }
+                        f.write_str(name)?;
+                        first = false;
+                    }
+                }
+                f.write_str(")")
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use {ExtentFlags, RwFlags};
+
+    #[test]
+    fn flag_operations() {
+        let flags = RwFlags::DSYNC | RwFlags::APPEND;
+        assert!(flags.contains(RwFlags::APPEND));
+        assert!(!flags.contains(RwFlags::DSYNC | RwFlags::SYNC));
+        assert_eq!(flags & RwFlags::APPEND, RwFlags::APPEND);
+        assert!((flags & RwFlags::NOWAIT).is_empty());
+        assert_eq!(RwFlags::from_bits(flags.bits()), Some(flags));
+        assert_eq!(RwFlags::from_bits(1 << 5), None);
+        let mut flags = ExtentFlags::empty();
+        flags |= ExtentFlags::SHARED;
+        assert_eq!(ExtentFlags::from_bits(1 << 9), Some(flags));
+        assert_eq!(ExtentFlags::from_bits(1 << 10), None);
+    }
+
+    #[test]
+    fn debug() {
+        assert_eq!(format!("{:?}", RwFlags::empty()), "RwFlags()");
+        assert_eq!(
+            format!("{:?}", RwFlags::HIPRI | RwFlags::NOWAIT),
+            "RwFlags(HIPRI | NOWAIT)"
+        );
+        assert_eq!(
+            format!("{:?}", ExtentFlags::UNKNOWN | ExtentFlags::DELALLOC),
+            "ExtentFlags(UNKNOWN | DELALLOC)"
+        );
+    }
+}
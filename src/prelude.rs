@@ -0,0 +1,16 @@
+//! Brings the extension traits into scope.
+//!
+//! Trait methods can only be called if the trait they belong to is in scope,
+//! and importing `FileExt` does not bring the methods of its supertraits into
+//! scope. Importing this module's contents makes all of them available:
+//!
+//! ```
+//! use file_offset::prelude::*;
+//! use std::fs::File;
+//!
+//! let mut buffer = [0; 16];
+//! let f = File::open("src/lib.rs").unwrap();
+//! f.read_exact_offset(&mut buffer, 3).unwrap();
+//! ```
+
+pub use {FileExt, ReadAt, ReadAtExt, WriteAt, WriteAtExt};
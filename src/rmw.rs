@@ -0,0 +1,529 @@
+use std::cmp;
+use std::io;
+use std::sync::{Condvar, Mutex};
+
+use space;
+use {lock_ignoring_poison, Advice, ReadAt, WriteAt};
+use {AlignedBuf, Alignment, DirectFile};
+
+// The maximum number of bytes transferred through the bounce buffer by a
+// single unaligned request.
+const MAX_BOUNCE: u64 = 1024 * 1024;
+
+/// A wrapper allowing unaligned positioned I/O on data that only supports
+/// aligned requests, such as a `DirectFile`.
+///
+/// Requests whose offset, length and buffer address are aligned are passed
+/// through unchanged. Other requests are widened to the surrounding aligned
+/// blocks and performed through an aligned bounce buffer. For writes, the
+/// partial blocks at the head and tail are read first, patched with the new
+/// data and written back.
+///
+/// Writes lock the blocks they touch for the duration of the
+/// read-modify-write cycle, so that concurrent unaligned writes to the same
+/// block through the same `UnalignedFile` do not lose updates. Reads are
+/// not locked, and may observe the zeros a write past the end of the data
+/// briefly leaves beyond its end. Unaligned requests transfer at most 1 MiB
+/// at a time, so `read_offset` and `write_offset` may return short counts
+/// for longer buffers.
+///
+/// ```no_run
+/// use file_offset::{DirectFile, UnalignedFile, WriteAt};
+/// use std::fs::OpenOptions;
+///
+/// let options = OpenOptions::new().read(true).write(true).clone();
+/// let file = UnalignedFile::direct(DirectFile::open("disk.img", &options).unwrap());
+/// file.write_all_offset(b"hello", 1000).unwrap();
+/// ```
+#[derive(Debug)]
+pub struct UnalignedFile<T> {
+    inner: T,
+    alignment: Alignment,
+    // The locked ranges of blocks, in bytes.
+    locked: Mutex<Vec<(u64, u64)>>,
+    unlocked: Condvar,
+}
+
+impl UnalignedFile<DirectFile> {
+    /// Wraps a `DirectFile`, using its alignment.
+    pub fn direct(file: DirectFile) -> UnalignedFile<DirectFile> {
+        let alignment = file.alignment();
+        UnalignedFile::new(file, alignment)
+    }
+}
+
+impl<T> UnalignedFile<T> {
+    /// Wraps `inner`, which only supports requests aligned to `alignment`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if either alignment is not a power of two.
+    pub fn new(inner: T, alignment: Alignment) -> UnalignedFile<T> {
+        assert!(
+            alignment.memory.is_power_of_two() && alignment.offset.is_power_of_two(),
+            "alignment must be a power of two"
+        );
+        UnalignedFile {
+            inner,
+            alignment,
+            locked: Mutex::new(Vec::new()),
+            unlocked: Condvar::new(),
+        }
+    }
+
+    /// Returns the alignment required by the underlying data.
+    pub fn alignment(&self) -> Alignment {
+        self.alignment
+    }
+
+    /// Returns a reference to the underlying data.
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    /// Consumes the wrapper, returning the underlying data.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    fn block_size(&self) -> u64 {
+        self.alignment.offset as u64
+    }
+
+    fn max_bounce(&self) -> u64 {
+        cmp::max(MAX_BOUNCE, self.block_size())
+    }
+
+    fn align_down(&self, offset: u64) -> u64 {
+        offset & !(self.block_size() - 1)
+    }
+
+    fn align_up(&self, offset: u64) -> io::Result<u64> {
+        match offset.checked_add(self.block_size() - 1) {
+            Some(offset) => Ok(self.align_down(offset)),
+            None => Err(overflow()),
+        }
+    }
+
+    fn is_aligned(&self, buf: &[u8], offset: u64) -> bool {
+        let block_size = self.block_size();
+        offset.is_multiple_of(block_size)
+            && (buf.len() as u64).is_multiple_of(block_size)
+            && (buf.as_ptr() as usize).is_multiple_of(self.alignment.memory)
+    }
+
+    // Locks the bytes `[start, end)`, waiting for overlapping locks to be
+    // released.
+    fn lock(&self, start: u64, end: u64) -> BlockLock<'_, T> {
+        let mut locked = lock_ignoring_poison(&self.locked);
+        while locked.iter().any(|&(s, e)| s < end && start < e) {
+            locked = self
+                .unlocked
+                .wait(locked)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+        locked.push((start, end));
+        BlockLock {
+            file: self,
+            range: (start, end),
+        }
+    }
+}
+
+fn checked_end(offset: u64, len: usize) -> io::Result<u64> {
+    offset.checked_add(len as u64).ok_or_else(overflow)
+}
+
+fn overflow() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, "offset overflow")
+}
+
+struct BlockLock<'a, T: 'a> {
+    file: &'a UnalignedFile<T>,
+    range: (u64, u64),
+}
+
+impl<'a, T> Drop for BlockLock<'a, T> {
+    fn drop(&mut self) {
+        let mut locked = lock_ignoring_poison(&self.file.locked);
+        if let Some(i) = locked.iter().position(|&r| r == self.range) {
+            locked.swap_remove(i);
+        }
+        self.file.unlocked.notify_all();
+    }
+}
+
+impl<T: ReadAt> UnalignedFile<T> {
+    // Reads into the aligned `buf` from the aligned `offset` until it is
+    // full or the end of the data is reached. Returns the number of bytes
+    // read; the rest of `buf` is left unchanged.
+    fn read_blocks(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        let mut read = 0;
+        while read < buf.len() {
+            match self
+                .inner
+                .read_offset(&mut buf[read..], offset + read as u64)
+            {
+                Ok(0) => break,
+                Ok(n) => {
+                    read += n;
+                    // A short read ending in the middle of a block means the
+                    // end of the data was reached.
+                    if !(read as u64).is_multiple_of(self.block_size()) {
+                        break;
+                    }
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(read)
+    }
+}
+
+impl<T: ReadAt> ReadAt for UnalignedFile<T> {
+    fn read_offset(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        if self.is_aligned(buf, offset) {
+            return self.inner.read_offset(buf, offset);
+        }
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let start = self.align_down(offset);
+        let head = (offset - start) as usize;
+        let len = cmp::min(buf.len() as u64, self.max_bounce() - head as u64) as usize;
+        let end = self.align_up(checked_end(offset, len)?)?;
+        let mut bounce = AlignedBuf::new((end - start) as usize, self.alignment.memory);
+        let read = self.read_blocks(&mut bounce, start)?;
+        let n = cmp::min(read.saturating_sub(head), len);
+        buf[..n].copy_from_slice(&bounce[head..head + n]);
+        Ok(n)
+    }
+    fn size(&self) -> io::Result<u64> {
+        self.inner.size()
+    }
+    fn advise_range(&self, offset: u64, len: u64, advice: Advice) -> io::Result<()> {
+        self.inner.advise_range(offset, len, advice)
+    }
+    fn readahead(&self, offset: u64, len: u64) -> io::Result<()> {
+        self.inner.readahead(offset, len)
+    }
+}
+
+impl<T: ReadAt + WriteAt> UnalignedFile<T> {
+    // Writes `buf`, which must fit into the bounce buffer, at the unaligned
+    // `offset` by reading the partial blocks at its head and tail, patching
+    // them and writing them back. `size` is the size of the data. The caller
+    // must hold a lock on the blocks, and on everything after them if the
+    // write extends the data.
+    fn write_bounced(&self, buf: &[u8], offset: u64, size: u64) -> io::Result<()> {
+        let start = self.align_down(offset);
+        let head = (offset - start) as usize;
+        let write_end = checked_end(offset, buf.len())?;
+        let end = self.align_up(write_end)?;
+        let block_size = self.block_size() as usize;
+        let mut bounce = AlignedBuf::new((end - start) as usize, self.alignment.memory);
+        if head != 0 {
+            self.read_blocks(&mut bounce[..block_size], start)?;
+        }
+        let tail = (write_end - start) as usize;
+        if write_end != end && (head == 0 || end - start > block_size as u64) {
+            let last = bounce.len() - block_size;
+            self.read_blocks(&mut bounce[last..], start + last as u64)?;
+        }
+        bounce[head..tail].copy_from_slice(buf);
+        self.inner
+            .write_all_offset(&bounce, start)
+            .map_err(io::Error::from)?;
+
+        // The bounce buffer extended the data to the end of the last block.
+        if end > size && write_end < end {
+            self.inner.set_size(cmp::max(size, write_end))?;
+        }
+        Ok(())
+    }
+
+    // Zeros `[offset, offset + len)`, passing the aligned middle of the range
+    // to `op` and zeroing the partial blocks around it through the bounce
+    // buffer. If `keep_size` is set, the data is not extended.
+    fn zero_unaligned<F>(&self, offset: u64, len: u64, keep_size: bool, op: F) -> io::Result<()>
+    where
+        F: FnOnce(&T, u64, u64) -> io::Result<()>,
+    {
+        let end = space::checked_end(offset, len)?;
+        let _lock = self.lock(self.align_down(offset), u64::MAX);
+        let middle = (self.align_up(offset)?, self.align_down(end));
+        let (head_end, tail_start) = if middle.0 < middle.1 {
+            op(&self.inner, middle.0, middle.1 - middle.0)?;
+            middle
+        } else {
+            (end, end)
+        };
+        for &(start, end) in &[(offset, head_end), (tail_start, end)] {
+            let size = self.inner.size()?;
+            let end = if keep_size { cmp::min(end, size) } else { end };
+            if start < end {
+                self.write_bounced(&vec![0; (end - start) as usize], start, size)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<T: ReadAt + WriteAt> WriteAt for UnalignedFile<T> {
+    fn write_offset(&self, buf: &[u8], offset: u64) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let aligned = self.is_aligned(buf, offset);
+        let start = self.align_down(offset);
+        let head = (offset - start) as usize;
+        let len = if aligned {
+            buf.len()
+        } else {
+            cmp::min(buf.len() as u64, self.max_bounce() - head as u64) as usize
+        };
+        let write_end = checked_end(offset, len)?;
+        let end = self.align_up(write_end)?;
+
+        // Writes that may change the size lock everything after their start,
+        // so that only one of them at a time determines the final size.
+        let mut lock = self.lock(start, end);
+        let mut size = self.inner.size()?;
+        if end > size {
+            drop(lock);
+            lock = self.lock(start, u64::MAX);
+            size = self.inner.size()?;
+        }
+
+        if aligned {
+            return self.inner.write_offset(buf, offset);
+        }
+        self.write_bounced(&buf[..len], offset, size)?;
+        drop(lock);
+        Ok(len)
+    }
+    fn set_size(&self, size: u64) -> io::Result<()> {
+        let _lock = self.lock(0, u64::MAX);
+        self.inner.set_size(size)
+    }
+    fn allocate_range(&self, offset: u64, len: u64) -> io::Result<()> {
+        let _lock = self.lock(self.align_down(offset), u64::MAX);
+        self.inner.allocate_range(offset, len)
+    }
+    /// Only the aligned middle of the range is passed on to the underlying
+    /// data. The partial blocks at its head and tail are overwritten with
+    /// zeros instead.
+    fn punch_hole(&self, offset: u64, len: u64) -> io::Result<()> {
+        self.zero_unaligned(offset, len, true, |inner, offset, len| {
+            inner.punch_hole(offset, len)
+        })
+    }
+    /// Only the aligned middle of the range is passed on to the underlying
+    /// data. The partial blocks at its head and tail are overwritten with
+    /// zeros instead.
+    fn zero_range(&self, offset: u64, len: u64) -> io::Result<()> {
+        self.zero_unaligned(offset, len, false, |inner, offset, len| {
+            inner.zero_range(offset, len)
+        })
+    }
+    fn collapse_range(&self, offset: u64, len: u64) -> io::Result<()> {
+        let _lock = self.lock(self.align_down(offset), u64::MAX);
+        self.inner.collapse_range(offset, len)
+    }
+    fn insert_range(&self, offset: u64, len: u64) -> io::Result<()> {
+        let _lock = self.lock(self.align_down(offset), u64::MAX);
+        self.inner.insert_range(offset, len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::sync::Mutex;
+    use std::thread;
+
+    use super::UnalignedFile;
+    use {Alignment, ReadAt, WriteAt};
+
+    const BLOCK: usize = 16;
+
+    // Data that only accepts requests aligned to `BLOCK`. Reads yield to
+    // other threads, widening the window for lost updates.
+    struct Strict(Mutex<Vec<u8>>);
+
+    fn check(len: usize, offset: u64) {
+        assert_eq!(len % BLOCK, 0, "unaligned length {}", len);
+        assert_eq!(offset % BLOCK as u64, 0, "unaligned offset {}", offset);
+    }
+
+    impl ReadAt for Strict {
+        fn read_offset(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+            check(buf.len(), offset);
+            let result = self.0.read_offset(buf, offset);
+            thread::yield_now();
+            result
+        }
+        fn size(&self) -> io::Result<u64> {
+            self.0.size()
+        }
+    }
+
+    impl WriteAt for Strict {
+        fn write_offset(&self, buf: &[u8], offset: u64) -> io::Result<usize> {
+            check(buf.len(), offset);
+            self.0.write_offset(buf, offset)
+        }
+        fn set_size(&self, size: u64) -> io::Result<()> {
+            self.0.set_size(size)
+        }
+        fn punch_hole(&self, offset: u64, len: u64) -> io::Result<()> {
+            check(len as usize, offset);
+            self.0.punch_hole(offset, len)
+        }
+        fn zero_range(&self, offset: u64, len: u64) -> io::Result<()> {
+            check(len as usize, offset);
+            self.0.zero_range(offset, len)
+        }
+    }
+
+    fn file(data: &[u8]) -> UnalignedFile<Strict> {
+        let alignment = Alignment {
+            memory: 1,
+            offset: BLOCK,
+        };
+        UnalignedFile::new(Strict(Mutex::new(data.to_vec())), alignment)
+    }
+
+    fn contents(file: UnalignedFile<Strict>) -> Vec<u8> {
+        file.into_inner().0.into_inner().unwrap()
+    }
+
+    fn pattern(len: usize) -> Vec<u8> {
+        (0..len).map(|i| i as u8).collect()
+    }
+
+    #[test]
+    fn unaligned_head() {
+        let file = file(&[0; 48]);
+        file.write_all_offset(&[1; 20], 12).unwrap();
+        let mut buf = [0; 22];
+        file.read_exact_offset(&mut buf, 11).unwrap();
+        assert_eq!(buf[0], 0);
+        assert_eq!(&buf[1..21], &[1; 20]);
+        assert_eq!(buf[21], 0);
+        let mut expected = vec![0; 48];
+        expected[12..32].copy_from_slice(&[1; 20]);
+        assert_eq!(contents(file), expected);
+    }
+
+    #[test]
+    fn unaligned_tail() {
+        let file = file(&pattern(48));
+        file.write_all_offset(&[0xff; 20], 16).unwrap();
+        let mut expected = pattern(48);
+        expected[16..36].copy_from_slice(&[0xff; 20]);
+        assert_eq!(contents(file), expected);
+    }
+
+    #[test]
+    fn within_single_block() {
+        let file = file(&pattern(48));
+        file.write_all_offset(b"abc", 20).unwrap();
+        let mut buf = [0; 5];
+        file.read_exact_offset(&mut buf, 19).unwrap();
+        assert_eq!(&buf, &[19, b'a', b'b', b'c', 23]);
+        let mut expected = pattern(48);
+        expected[20..23].copy_from_slice(b"abc");
+        assert_eq!(contents(file), expected);
+    }
+
+    #[test]
+    fn extending_write() {
+        let file = file(&pattern(20));
+        file.write_all_offset(b"xyz", 30).unwrap();
+        assert_eq!(file.size().unwrap(), 33);
+        let mut expected = pattern(20);
+        expected.resize(30, 0);
+        expected.extend_from_slice(b"xyz");
+        assert_eq!(contents(file), expected);
+    }
+
+    #[test]
+    fn write_offset_overflow() {
+        let alignment = Alignment {
+            memory: 1,
+            offset: BLOCK,
+        };
+        let file = UnalignedFile::new(Mutex::new(vec![0; 32]), alignment);
+        let err = file.write_offset(b"abc", u64::MAX - 5).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        let err = file.write_offset(b"abc", u64::MAX - 1).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        let err = file.read_offset(&mut [0; 3], u64::MAX - 5).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(file.into_inner().into_inner().unwrap(), [0; 32]);
+    }
+
+    #[test]
+    fn punch_hole() {
+        for &(offset, len) in &[(10, 4), (16, 32), (10, 40), (3, 26), (16, 5)] {
+            let file = file(&pattern(64));
+            file.punch_hole(offset, len).unwrap();
+            let mut expected = pattern(64);
+            for b in &mut expected[offset as usize..(offset + len) as usize] {
+                *b = 0;
+            }
+            assert_eq!(contents(file), expected, "{} {}", offset, len);
+        }
+    }
+
+    #[test]
+    fn punch_hole_past_end() {
+        let file = file(&pattern(40));
+        file.punch_hole(30, 100).unwrap();
+        file.punch_hole(50, 10).unwrap();
+        let mut expected = pattern(40);
+        expected[30..].copy_from_slice(&[0; 10]);
+        assert_eq!(contents(file), expected);
+    }
+
+    #[test]
+    fn zero_range() {
+        let file = file(&pattern(64));
+        file.zero_range(3, 5).unwrap();
+        file.zero_range(30, 20).unwrap();
+        let mut expected = pattern(64);
+        expected[3..8].copy_from_slice(&[0; 5]);
+        expected[30..50].copy_from_slice(&[0; 20]);
+        assert_eq!(contents(file), expected);
+    }
+
+    #[test]
+    fn zero_range_past_end() {
+        let file = file(&pattern(20));
+        file.zero_range(10, 30).unwrap();
+        assert_eq!(file.size().unwrap(), 40);
+        let mut expected = pattern(10);
+        expected.resize(40, 0);
+        assert_eq!(contents(file), expected);
+    }
+
+    #[test]
+    fn concurrent_overlapping_writes() {
+        const THREADS: usize = 8;
+        const ROUNDS: u8 = 250;
+        let file = file(&[]);
+        thread::scope(|s| {
+            for i in 0..THREADS {
+                let file = &file;
+                s.spawn(move || {
+                    for round in 1..=ROUNDS {
+                        file.write_all_offset(&[round; 3], 3 * i as u64).unwrap();
+                    }
+                });
+            }
+        });
+        assert_eq!(contents(file), vec![ROUNDS; 3 * THREADS]);
+    }
+}
@@ -0,0 +1,186 @@
+use std::cmp;
+use std::fs::File;
+use std::io;
+use std::os::unix::io::{AsFd, BorrowedFd};
+
+use sys;
+use {ReadAt, BUFFER_SIZE};
+
+// The maximum number of bytes sent by a single `sendfile` or `splice` call.
+const MAX_KERNEL_SEND: u64 = 1 << 30;
+
+/// Sends up to `len` bytes of `file`, starting at `offset`, to a socket or
+/// pipe.
+///
+/// Returns the number of bytes sent. Short sends are continued until `len`
+/// bytes have been sent, and interrupted calls are retried. Fewer bytes are
+/// sent only if the end of the file is reached, or if `out` is non-blocking
+/// and would block after some data has been sent. If it would block before
+/// any data has been sent, an error of kind `ErrorKind::WouldBlock` is
+/// returned. In both cases, the send can be resumed at `offset` plus the
+/// number of bytes sent. The file's cursor is not used.
+///
+/// ```
+/// use file_offset::{send_range_to, ReadAt};
+/// use std::fs::File;
+/// use std::io::Read;
+/// use std::os::unix::net::UnixStream;
+///
+/// let f = File::open("src/lib.rs").unwrap();
+/// let (tx, mut rx) = UnixStream::pair().unwrap();
+/// assert_eq!(send_range_to(&f, 3, 8, &tx).unwrap(), 8);
+/// let (mut received, mut expected) = ([0; 8], [0; 8]);
+/// rx.read_exact(&mut received).unwrap();
+/// f.read_exact_offset(&mut expected, 3).unwrap();
+/// assert_eq!(received, expected);
+/// ```
+///
+/// # Platform-specific behavior
+///
+/// On Linux and Android, this uses the `splice` function if `out` is a pipe
+/// and the `sendfile` function otherwise, so the data does not pass through
+/// userspace. If these are not supported for the given descriptors, and on
+/// other Unix platforms, the data is read with `read_offset` and written to
+/// `out` through a buffer. This function is not available on Windows.
+pub fn send_range_to<O: AsFd + ?Sized>(
+    file: &File,
+    mut offset: u64,
+    len: u64,
+    out: &O,
+) -> io::Result<u64> {
+    let out = out.as_fd();
+    let pipe = sys::is_pipe(out)?;
+    let mut sent = 0;
+    let mut kernel = true;
+    let mut buf = Vec::new();
+    while sent < len {
+        let remaining = len - sent;
+        let result = if kernel {
+            let chunk = cmp::min(remaining, MAX_KERNEL_SEND) as usize;
+            match sys::send_file(file, offset, out, pipe, chunk) {
+                Err(ref e) if e.kind() == io::ErrorKind::Unsupported => {
+                    kernel = false;
+                    continue;
+                }
+                result => result,
+            }
+        } else {
+            if buf.is_empty() {
+                buf = vec![0; cmp::min(remaining, BUFFER_SIZE as u64) as usize];
+            }
+            let chunk = cmp::min(remaining, buf.len() as u64) as usize;
+            send_buffered(file, offset, out, &mut buf[..chunk])
+        };
+        match result {
+            Ok(0) => break,
+            Ok(n) => {
+                sent += n as u64;
+                offset += n as u64;
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock && sent > 0 => break,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(sent)
+}
+
+// Reads a chunk of the file and writes as much of it to `out` as possible.
+// Data that could not be written is simply read again by the next call.
+fn send_buffered(file: &File, offset: u64, out: BorrowedFd, buf: &mut [u8]) -> io::Result<usize> {
+    let n = file.read_offset(buf, offset)?;
+    let mut written = 0;
+    while written < n {
+        match sys::write_fd(out, &buf[written..n]) {
+            Ok(0) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::WriteZero,
+                    "failed to write whole buffer",
+                ))
+            }
+            Ok(m) => written += m,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock && written > 0 => break,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use libc;
+    use std::io::{self, Read};
+    use std::os::unix::io::{AsRawFd, RawFd};
+    use std::thread;
+
+    use super::send_range_to;
+    use test_util::temp_file;
+    use ReadAt;
+
+    const LEN: usize = 300_000;
+
+    fn pattern() -> Vec<u8> {
+        (0..LEN).map(|i| (i % 251) as u8).collect()
+    }
+
+    fn add_flags(fd: RawFd, flags: libc::c_int) {
+        unsafe {
+            let old = libc::fcntl(fd, libc::F_GETFL);
+            assert!(old >= 0);
+            assert_eq!(libc::fcntl(fd, libc::F_SETFL, old | flags), 0);
+        }
+    }
+
+    #[test]
+    fn pipe() {
+        let data = pattern();
+        let file = temp_file(&data);
+        let (mut rx, tx) = io::pipe().unwrap();
+        let reader = thread::spawn(move || {
+            let mut received = Vec::new();
+            rx.read_to_end(&mut received).unwrap();
+            received
+        });
+        assert_eq!(
+            send_range_to(&file, 7, LEN as u64, &tx).unwrap(),
+            LEN as u64 - 7
+        );
+        drop(tx);
+        assert_eq!(reader.join().unwrap(), data[7..]);
+    }
+
+    #[test]
+    fn non_blocking_pipe() {
+        let data = pattern();
+        let file = temp_file(&data);
+        let (mut rx, tx) = io::pipe().unwrap();
+        add_flags(tx.as_raw_fd(), libc::O_NONBLOCK);
+        let sent = send_range_to(&file, 0, LEN as u64, &tx).unwrap();
+        assert!(sent > 0 && sent < LEN as u64);
+        let err = send_range_to(&file, sent, LEN as u64, &tx).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+        drop(tx);
+        let mut received = Vec::new();
+        rx.read_to_end(&mut received).unwrap();
+        assert_eq!(received, data[..sent as usize]);
+    }
+
+    // `sendfile` rejects descriptors opened for appending, so the data is
+    // written through a buffer.
+    #[test]
+    fn buffered_fallback() {
+        let data = pattern();
+        let file = temp_file(&data);
+        let out = temp_file(b"abc");
+        add_flags(out.as_raw_fd(), libc::O_APPEND);
+        assert_eq!(
+            send_range_to(&file, 0, LEN as u64 + 1, &out).unwrap(),
+            LEN as u64
+        );
+        let mut written = vec![0; LEN + 3];
+        out.read_exact_offset(&mut written, 0).unwrap();
+        assert_eq!(&written[..3], b"abc");
+        assert_eq!(written[3..], data[..]);
+    }
+}
@@ -0,0 +1,376 @@
+use std::cmp;
+use std::io;
+
+use batch;
+use {Advice, ReadAt, ReadBuf, ReadBufCursor, RwFlags, WriteAt};
+
+/// A view of the byte range `[start, start + len)` of an underlying
+/// `ReadAt` and/or `WriteAt` implementor.
+///
+/// Offsets passed to the positioned I/O methods are relative to the start of
+/// the range. Reads are clamped at the end of the range, as if the range
+/// ended the file. Writes are not allowed to extend past the end of the
+/// range: a write that straddles the end is shortened, and a write that
+/// starts at or past the end fails with an error of kind
+/// `ErrorKind::FileTooLarge`.
+///
+/// Slices can themselves be sliced further using `slice` and `split_at`.
+///
+/// ```
+/// use file_offset::{FileSlice, ReadAt};
+///
+/// let data: &[u8] = b"header:payload";
+/// let payload = FileSlice::new(data, 7, 7);
+/// let mut buf = [0; 16];
+/// assert_eq!(payload.read_offset(&mut buf, 0).unwrap(), 7);
+/// assert_eq!(&buf[..7], b"payload");
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct FileSlice<T> {
+    inner: T,
+    start: u64,
+    len: u64,
+}
+
+impl<T> FileSlice<T> {
+    /// Creates a view of the range `[start, start + len)` of `inner`.
+    ///
+    /// The range is not checked against the size of `inner`. If `inner` is
+    /// shorter than the range, reads return less data accordingly.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `start + len` overflows a `u64`.
+    pub fn new(inner: T, start: u64, len: u64) -> FileSlice<T> {
+        assert!(
+            start.checked_add(len).is_some(),
+            "file slice range overflows u64"
+        );
+        FileSlice { inner, start, len }
+    }
+
+    /// Returns the offset of the start of this slice in the underlying
+    /// data.
+    pub fn start(&self) -> u64 {
+        self.start
+    }
+
+    /// Returns the length of this slice.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns `true` if this slice has a length of zero.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns a reference to the underlying data.
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    /// Consumes the slice, returning the underlying data.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    /// Returns a view of the range `[start, start + len)` of this slice.
+    ///
+    /// The returned slice refers to the same underlying data directly, so
+    /// nested slices add no overhead. Returns `None` if the range does not
+    /// lie within this slice.
+    pub fn slice(&self, start: u64, len: u64) -> Option<FileSlice<&T>> {
+        let end = start.checked_add(len)?;
+        if end > self.len {
+            return None;
+        }
+        Some(FileSlice {
+            inner: &self.inner,
+            start: self.start + start,
+            len,
+        })
+    }
+
+    /// Splits this slice into two at the given offset.
+    ///
+    /// The first slice contains `[0, mid)`, the second `[mid, len)`. Returns
+    /// `None` if `mid` is greater than the length of this slice.
+    pub fn split_at(&self, mid: u64) -> Option<(FileSlice<&T>, FileSlice<&T>)> {
+        let first = self.slice(0, mid)?;
+        let second = self.slice(mid, self.len - mid)?;
+        Some((first, second))
+    }
+
+    // Returns the length a transfer of `len` bytes at `offset` can have
+    // without crossing the end of the slice, or `None` if `offset` lies at or
+    // past the end.
+    fn clamp(&self, offset: u64, len: usize) -> Option<usize> {
+        if offset >= self.len {
+            return None;
+        }
+        Some(cmp::min(len as u64, self.len - offset) as usize)
+    }
+
+    // Checks that `[offset, offset + len)` lies within the slice and returns
+    // `offset` translated to the underlying data.
+    fn check_range(&self, offset: u64, len: u64) -> io::Result<u64> {
+        match offset.checked_add(len) {
+            Some(end) if end <= self.len => Ok(self.start + offset),
+            _ => Err(write_past_end()),
+        }
+    }
+}
+
+fn write_past_end() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::FileTooLarge,
+        "write past the end of the file slice",
+    )
+}
+
+impl<T: ReadAt> ReadAt for FileSlice<T> {
+    fn read_offset(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        match self.clamp(offset, buf.len()) {
+            Some(n) => self.inner.read_offset(&mut buf[..n], self.start + offset),
+            None => Ok(0),
+        }
+    }
+    fn size(&self) -> io::Result<u64> {
+        let inner = self.inner.size()?;
+        Ok(cmp::min(self.len, inner.saturating_sub(self.start)))
+    }
+    fn read_buf_offset(&self, mut buf: ReadBufCursor, offset: u64) -> io::Result<()> {
+        let n = match self.clamp(offset, buf.capacity()) {
+            Some(n) => n,
+            None => return Ok(()),
+        };
+        // Read into a `ReadBuf` limited to the clamped length. Anything it
+        // reports as filled is initialized in `buf` as well.
+        let filled = unsafe {
+            let mut limited = ReadBuf::uninit(&mut buf.unfilled_mut()[..n]);
+            self.inner
+                .read_buf_offset(limited.unfilled(), self.start + offset)?;
+            limited.filled().len()
+        };
+        unsafe {
+            buf.assume_init(filled);
+        }
+        buf.advance(filled);
+        Ok(())
+    }
+    fn read_offset_with_flags(
+        &self,
+        buf: &mut [u8],
+        offset: u64,
+        flags: RwFlags,
+    ) -> io::Result<usize> {
+        match self.clamp(offset, buf.len()) {
+            Some(n) => self
+                .inner
+                .read_offset_with_flags(&mut buf[..n], self.start + offset, flags),
+            None => Ok(0),
+        }
+    }
+    fn read_offsets_batch(&self, reqs: &mut [(u64, &mut [u8])]) -> Vec<io::Result<usize>> {
+        let reqs =
+            reqs.iter_mut().map(
+                |&mut (offset, ref mut buf)| match self.clamp(offset, buf.len()) {
+                    Some(n) => Ok((self.start + offset, &mut buf[..n])),
+                    None => Err(Ok(0)),
+                },
+            );
+        batch::forward_batch(reqs, |reqs| self.inner.read_offsets_batch(reqs))
+    }
+    /// The range is clamped at the end of the slice.
+    fn advise_range(&self, offset: u64, len: u64, advice: Advice) -> io::Result<()> {
+        if offset >= self.len {
+            return Ok(());
+        }
+        let len = match len {
+            0 => self.len - offset,
+            len => cmp::min(len, self.len - offset),
+        };
+        self.inner.advise_range(self.start + offset, len, advice)
+    }
+    /// The range is clamped at the end of the slice.
+    fn readahead(&self, offset: u64, len: u64) -> io::Result<()> {
+        if offset >= self.len {
+            return Ok(());
+        }
+        let len = cmp::min(len, self.len - offset);
+        self.inner.readahead(self.start + offset, len)
+    }
+}
+
+impl<T: WriteAt> WriteAt for FileSlice<T> {
+    fn write_offset(&self, buf: &[u8], offset: u64) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        match self.clamp(offset, buf.len()) {
+            Some(n) => self.inner.write_offset(&buf[..n], self.start + offset),
+            None => Err(write_past_end()),
+        }
+    }
+    /// Slices cannot be resized, so this fails with `ErrorKind::Unsupported`.
+    fn set_size(&self, _size: u64) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "file slices cannot be resized",
+        ))
+    }
+    fn write_offset_with_flags(
+        &self,
+        buf: &[u8],
+        offset: u64,
+        flags: RwFlags,
+    ) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        // Appending would write outside of the slice.
+        if flags.contains(RwFlags::APPEND) {
+            return Err(write_past_end());
+        }
+        match self.clamp(offset, buf.len()) {
+            Some(n) => self
+                .inner
+                .write_offset_with_flags(&buf[..n], self.start + offset, flags),
+            None => Err(write_past_end()),
+        }
+    }
+    fn write_offsets_batch(&self, reqs: &[(u64, &[u8])]) -> Vec<io::Result<usize>> {
+        let reqs = reqs.iter().map(|&(offset, buf)| {
+            if buf.is_empty() {
+                return Err(Ok(0));
+            }
+            match self.clamp(offset, buf.len()) {
+                Some(n) => Ok((self.start + offset, &buf[..n])),
+                None => Err(Err(write_past_end())),
+            }
+        });
+        batch::forward_batch(reqs, |reqs| self.inner.write_offsets_batch(reqs))
+    }
+    /// Fails with `ErrorKind::FileTooLarge` if the range extends past the
+    /// end of the slice.
+    fn allocate_range(&self, offset: u64, len: u64) -> io::Result<()> {
+        let offset = self.check_range(offset, len)?;
+        self.inner.allocate_range(offset, len)
+    }
+    /// The range is clamped at the end of the slice.
+    fn punch_hole(&self, offset: u64, len: u64) -> io::Result<()> {
+        if offset >= self.len {
+            return Ok(());
+        }
+        let len = cmp::min(len, self.len - offset);
+        self.inner.punch_hole(self.start + offset, len)
+    }
+    /// Fails with `ErrorKind::FileTooLarge` if the range extends past the
+    /// end of the slice.
+    fn zero_range(&self, offset: u64, len: u64) -> io::Result<()> {
+        let offset = self.check_range(offset, len)?;
+        self.inner.zero_range(offset, len)
+    }
+    /// Slices cannot be resized, so this fails with `ErrorKind::Unsupported`.
+    fn collapse_range(&self, _offset: u64, _len: u64) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "file slices cannot be resized",
+        ))
+    }
+    /// Slices cannot be resized, so this fails with `ErrorKind::Unsupported`.
+    fn insert_range(&self, _offset: u64, _len: u64) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "file slices cannot be resized",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::sync::Mutex;
+
+    use super::FileSlice;
+    use {ReadAt, WriteAt};
+
+    #[test]
+    fn read_clamped_at_end() {
+        let data: &[u8] = b"0123456789";
+        let slice = FileSlice::new(data, 2, 5);
+        let mut buf = [0; 8];
+        assert_eq!(slice.read_offset(&mut buf, 3).unwrap(), 2);
+        assert_eq!(&buf[..2], b"56");
+        assert_eq!(slice.read_offset(&mut buf, 5).unwrap(), 0);
+        assert_eq!(slice.read_offset(&mut buf, u64::MAX).unwrap(), 0);
+        assert_eq!(slice.size().unwrap(), 5);
+        let err = slice.read_exact_offset(&mut buf, 0).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(err.transferred(), 5);
+        assert_eq!(FileSlice::new(data, 8, 5).size().unwrap(), 2);
+    }
+
+    #[test]
+    fn write_straddling_end_is_shortened() {
+        let data = Mutex::new(b"0123456789".to_vec());
+        let slice = FileSlice::new(&data, 2, 5);
+        assert_eq!(slice.write_offset(b"abcd", 3).unwrap(), 2);
+        let err = slice.write_all_offset(b"xyz", 4).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::FileTooLarge);
+        assert_eq!(err.transferred(), 1);
+        assert_eq!(*data.lock().unwrap(), b"01234ax789");
+    }
+
+    #[test]
+    fn write_past_end() {
+        let data = Mutex::new(b"0123456789".to_vec());
+        let slice = FileSlice::new(&data, 2, 5);
+        for &offset in &[5, 6, u64::MAX] {
+            let err = slice.write_offset(b"a", offset).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::FileTooLarge);
+        }
+        assert_eq!(slice.write_offset(b"", 10).unwrap(), 0);
+        let results = slice.write_offsets_batch(&[(0, b"a"), (5, b"b"), (4, b"cd")]);
+        assert_eq!(results[0].as_ref().unwrap(), &1);
+        assert_eq!(
+            results[1].as_ref().unwrap_err().kind(),
+            io::ErrorKind::FileTooLarge
+        );
+        assert_eq!(results[2].as_ref().unwrap(), &1);
+        assert_eq!(*data.lock().unwrap(), b"01a345c789");
+    }
+
+    #[test]
+    fn nested_slice() {
+        let data: &[u8] = b"0123456789";
+        let outer = FileSlice::new(data, 1, 8);
+        let inner = outer.slice(2, 4).unwrap();
+        assert_eq!(inner.start(), 3);
+        assert_eq!(inner.len(), 4);
+        let mut buf = [0; 8];
+        assert_eq!(inner.read_offset(&mut buf, 1).unwrap(), 3);
+        assert_eq!(&buf[..3], b"456");
+        let innermost = inner.slice(3, 1).unwrap();
+        assert_eq!(innermost.start(), 6);
+        assert!(inner.slice(3, 2).is_none());
+        assert!(outer.slice(9, 0).is_none());
+        assert!(outer.slice(1, u64::MAX).is_none());
+        assert!(outer.slice(8, 0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn split_at() {
+        let data: &[u8] = b"0123456789";
+        let slice = FileSlice::new(data, 2, 6);
+        let (first, second) = slice.split_at(2).unwrap();
+        assert_eq!((first.start(), first.len()), (2, 2));
+        assert_eq!((second.start(), second.len()), (4, 4));
+        let mut buf = [0; 4];
+        second.read_exact_offset(&mut buf, 0).unwrap();
+        assert_eq!(&buf, b"4567");
+        assert!(slice.split_at(6).unwrap().1.is_empty());
+        assert!(slice.split_at(7).is_none());
+    }
+}
@@ -0,0 +1,33 @@
+use std::cmp;
+use std::io;
+
+use {WriteAt, BUFFER_SIZE};
+
+static ZEROS: [u8; BUFFER_SIZE] = [0; BUFFER_SIZE];
+
+/// Writes `len` zero bytes starting at `offset`.
+///
+/// This is used to emulate operations like `WriteAt::zero_range` where the
+/// underlying data does not support them.
+pub fn write_zeros<W: WriteAt + ?Sized>(w: &W, mut offset: u64, mut len: u64) -> io::Result<()> {
+    while len > 0 {
+        let chunk = cmp::min(len, ZEROS.len() as u64) as usize;
+        w.write_all_offset(&ZEROS[..chunk], offset)?;
+        offset += chunk as u64;
+        len -= chunk as u64;
+    }
+    Ok(())
+}
+
+pub fn unsupported(what: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("{} is not supported", what),
+    )
+}
+
+pub fn checked_end(offset: u64, len: u64) -> io::Result<u64> {
+    offset
+        .checked_add(len)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "range end overflows u64"))
+}
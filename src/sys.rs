@@ -4,11 +4,99 @@ pub use self::unix::*;
 #[cfg(windows)]
 pub use self::windows::*;
 
+/// The operations performed by `fallocate`.
+#[derive(Clone, Copy)]
+pub enum Fallocate {
+    Allocate,
+    PunchHole,
+    ZeroRange,
+    CollapseRange,
+    InsertRange,
+}
+
 #[cfg(unix)]
 mod unix {
-    use std::fs::File;
+    use libc;
+    use std::cmp;
+    use std::fs::{File, Metadata, OpenOptions};
     use std::io;
-    use std::os::unix::fs::FileExt;
+    use std::io::{IoSlice, IoSliceMut};
+    use std::mem;
+    use std::os::unix::fs::{FileExt, MetadataExt};
+    use std::os::unix::io::{AsRawFd, BorrowedFd};
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    use std::ptr;
+
+    use self::lfs::off_t;
+    use super::Fallocate;
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    use space;
+    use {Advice, Alignment, Extent, LockKind, ReadBufCursor, RwFlags};
+
+    // The functions taking file offsets. On 32-bit glibc targets, `off_t` is
+    // only 32 bits wide, so the large-file variants are used instead, like
+    // `std` does. Other C libraries always use 64-bit offsets.
+    #[cfg(all(target_os = "linux", target_env = "gnu"))]
+    mod lfs {
+        pub use libc::{
+            fallocate64 as fallocate, flock64 as flock, lseek64 as lseek, off64_t as off_t,
+            posix_fadvise64 as posix_fadvise, pread64 as pread, preadv64 as preadv,
+            pwritev64 as pwritev, sendfile64 as sendfile,
+        };
+        #[cfg(target_pointer_width = "64")]
+        pub use libc::{fcntl, F_SETLK, F_SETLKW};
+
+        // The `fcntl` function of 32-bit targets expects a `struct flock`
+        // for locking commands, so the system call taking a `struct flock64`
+        // is invoked directly. Its commands for process-associated locks
+        // differ, those for open file description locks do not.
+        #[cfg(target_pointer_width = "32")]
+        pub unsafe fn fcntl(fd: libc::c_int, cmd: libc::c_int, lock: *const flock) -> libc::c_int {
+            #[cfg(any(target_arch = "riscv32", target_arch = "csky"))]
+            let nr = libc::SYS_fcntl;
+            #[cfg(not(any(target_arch = "riscv32", target_arch = "csky")))]
+            let nr = libc::SYS_fcntl64;
+            libc::syscall(nr, fd, cmd, lock) as libc::c_int
+        }
+        #[cfg(all(
+            target_pointer_width = "32",
+            any(target_arch = "mips", target_arch = "mips32r6")
+        ))]
+        pub const F_SETLK: libc::c_int = 34;
+        #[cfg(all(
+            target_pointer_width = "32",
+            any(target_arch = "mips", target_arch = "mips32r6")
+        ))]
+        pub const F_SETLKW: libc::c_int = 35;
+        #[cfg(all(
+            target_pointer_width = "32",
+            not(any(target_arch = "mips", target_arch = "mips32r6"))
+        ))]
+        pub const F_SETLK: libc::c_int = 13;
+        #[cfg(all(
+            target_pointer_width = "32",
+            not(any(target_arch = "mips", target_arch = "mips32r6"))
+        ))]
+        pub const F_SETLKW: libc::c_int = 14;
+    }
+
+    #[cfg(not(all(target_os = "linux", target_env = "gnu")))]
+    mod lfs {
+        #[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+        pub use libc::posix_fadvise;
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        pub use libc::{fallocate, lseek, sendfile};
+        pub use libc::{fcntl, flock, off_t, pread, F_SETLK, F_SETLKW};
+        #[cfg(any(
+            target_os = "linux",
+            target_os = "android",
+            target_os = "freebsd",
+            target_os = "dragonfly",
+            target_os = "netbsd",
+            target_os = "openbsd"
+        ))]
+        pub use libc::{preadv, pwritev};
+    }
 
     #[inline]
     pub fn read_offset(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
@@ -19,14 +107,834 @@ mod unix {
     pub fn write_offset(file: &File, buf: &[u8], offset: u64) -> io::Result<usize> {
         file.write_at(buf, offset)
     }
+
+    // The maximum number of bytes that can be read in a single call. Larger
+    // reads fail with `EINVAL` on some platforms.
+    #[cfg(any(target_os = "macos", target_os = "ios"))]
+    const READ_LIMIT: usize = libc::c_int::MAX as usize - 1;
+    #[cfg(not(any(target_os = "macos", target_os = "ios")))]
+    const READ_LIMIT: usize = libc::ssize_t::MAX as usize;
+
+    pub fn read_buf_offset(file: &File, mut buf: ReadBufCursor, offset: u64) -> io::Result<()> {
+        let offset = cvt_offset(offset)?;
+        unsafe {
+            let dst = buf.unfilled_mut();
+            let n = cvt(lfs::pread(
+                file.as_raw_fd(),
+                dst.as_mut_ptr() as *mut libc::c_void,
+                cmp::min(dst.len(), READ_LIMIT),
+                offset,
+            ))?;
+            buf.assume_init(n);
+            buf.advance(n);
+        }
+        Ok(())
+    }
+
+    // The maximum number of buffers that can be passed to a single
+    // `preadv`/`pwritev` call. POSIX only guarantees 16, but every platform
+    // we use these functions on supports at least 1024.
+    #[cfg(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "freebsd",
+        target_os = "dragonfly",
+        target_os = "netbsd",
+        target_os = "openbsd"
+    ))]
+    const IOV_MAX: usize = 1024;
+
+    fn cvt_offset(offset: u64) -> io::Result<off_t> {
+        if offset > off_t::MAX as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "offset too large for this platform",
+            ));
+        }
+        Ok(offset as off_t)
+    }
+
+    fn cvt(ret: libc::ssize_t) -> io::Result<usize> {
+        if ret < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(ret as usize)
+        }
+    }
+
+    #[cfg(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "freebsd",
+        target_os = "dragonfly",
+        target_os = "netbsd",
+        target_os = "openbsd"
+    ))]
+    pub fn read_vectored_offset(
+        file: &File,
+        bufs: &mut [IoSliceMut],
+        offset: u64,
+    ) -> io::Result<usize> {
+        // `IoSliceMut` is guaranteed to be ABI compatible with `iovec`.
+        cvt(unsafe {
+            lfs::preadv(
+                file.as_raw_fd(),
+                bufs.as_mut_ptr() as *const libc::iovec,
+                cmp::min(bufs.len(), IOV_MAX) as libc::c_int,
+                cvt_offset(offset)?,
+            )
+        })
+    }
+
+    #[cfg(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "freebsd",
+        target_os = "dragonfly",
+        target_os = "netbsd",
+        target_os = "openbsd"
+    ))]
+    pub fn write_vectored_offset(file: &File, bufs: &[IoSlice], offset: u64) -> io::Result<usize> {
+        // `IoSlice` is guaranteed to be ABI compatible with `iovec`.
+        cvt(unsafe {
+            lfs::pwritev(
+                file.as_raw_fd(),
+                bufs.as_ptr() as *const libc::iovec,
+                cmp::min(bufs.len(), IOV_MAX) as libc::c_int,
+                cvt_offset(offset)?,
+            )
+        })
+    }
+
+    #[cfg(not(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "freebsd",
+        target_os = "dragonfly",
+        target_os = "netbsd",
+        target_os = "openbsd"
+    )))]
+    pub fn read_vectored_offset(
+        file: &File,
+        bufs: &mut [IoSliceMut],
+        offset: u64,
+    ) -> io::Result<usize> {
+        let buf = bufs
+            .iter_mut()
+            .find(|b| !b.is_empty())
+            .map_or(&mut [][..], |b| &mut **b);
+        read_offset(file, buf, offset)
+    }
+
+    #[cfg(not(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "freebsd",
+        target_os = "dragonfly",
+        target_os = "netbsd",
+        target_os = "openbsd"
+    )))]
+    pub fn write_vectored_offset(file: &File, bufs: &[IoSlice], offset: u64) -> io::Result<usize> {
+        let buf = bufs
+            .iter()
+            .find(|b| !b.is_empty())
+            .map_or(&[][..], |b| &**b);
+        write_offset(file, buf, offset)
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn cvt_flags(flags: RwFlags) -> libc::c_int {
+        let mut result = 0;
+        if flags.contains(RwFlags::HIPRI) {
+            result |= libc::RWF_HIPRI;
+        }
+        if flags.contains(RwFlags::DSYNC) {
+            result |= libc::RWF_DSYNC;
+        }
+        if flags.contains(RwFlags::SYNC) {
+            result |= libc::RWF_SYNC;
+        }
+        if flags.contains(RwFlags::NOWAIT) {
+            result |= libc::RWF_NOWAIT;
+        }
+        if flags.contains(RwFlags::APPEND) {
+            result |= libc::RWF_APPEND;
+        }
+        result
+    }
+
+    // Kernels before 4.6 lack the system calls altogether, later kernels
+    // reject flags they don't know about with `EOPNOTSUPP`.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn cvt_flags_error(err: io::Error) -> io::Error {
+        match err.raw_os_error() {
+            Some(libc::ENOSYS) => io::Error::new(
+                io::ErrorKind::Unsupported,
+                "preadv2/pwritev2 are not supported by this kernel",
+            ),
+            Some(libc::EOPNOTSUPP) => io::Error::new(
+                io::ErrorKind::Unsupported,
+                "flags are not supported by this kernel or file",
+            ),
+            _ => err,
+        }
+    }
+
+    // The system calls are invoked directly, as the libc wrappers are only
+    // available in sufficiently recent C libraries. The offset is passed
+    // split into its low and high halves, as expected by the kernel.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn read_offset_with_flags(
+        file: &File,
+        buf: &mut [u8],
+        offset: u64,
+        flags: RwFlags,
+    ) -> io::Result<usize> {
+        let iov = libc::iovec {
+            iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+            iov_len: buf.len(),
+        };
+        cvt_offset(offset)?;
+        let ret = unsafe {
+            libc::syscall(
+                libc::SYS_preadv2,
+                file.as_raw_fd(),
+                &iov as *const libc::iovec,
+                1 as libc::c_int,
+                offset as libc::c_ulong,
+                (offset >> 32) as libc::c_ulong,
+                cvt_flags(flags),
+            )
+        };
+        cvt(ret as libc::ssize_t).map_err(cvt_flags_error)
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn write_offset_with_flags(
+        file: &File,
+        buf: &[u8],
+        offset: u64,
+        flags: RwFlags,
+    ) -> io::Result<usize> {
+        let iov = libc::iovec {
+            iov_base: buf.as_ptr() as *mut libc::c_void,
+            iov_len: buf.len(),
+        };
+        cvt_offset(offset)?;
+        let ret = unsafe {
+            libc::syscall(
+                libc::SYS_pwritev2,
+                file.as_raw_fd(),
+                &iov as *const libc::iovec,
+                1 as libc::c_int,
+                offset as libc::c_ulong,
+                (offset >> 32) as libc::c_ulong,
+                cvt_flags(flags),
+            )
+        };
+        cvt(ret as libc::ssize_t).map_err(cvt_flags_error)
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    pub fn read_offset_with_flags(
+        file: &File,
+        buf: &mut [u8],
+        offset: u64,
+        flags: RwFlags,
+    ) -> io::Result<usize> {
+        if !flags.is_empty() {
+            return Err(::flags::unsupported());
+        }
+        read_offset(file, buf, offset)
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    pub fn write_offset_with_flags(
+        file: &File,
+        buf: &[u8],
+        offset: u64,
+        flags: RwFlags,
+    ) -> io::Result<usize> {
+        if !flags.is_empty() {
+            return Err(::flags::unsupported());
+        }
+        write_offset(file, buf, offset)
+    }
+
+    // `copy_file_range` fails with these errors if it cannot be used for the
+    // given files, e.g. because they reside on different file systems on
+    // kernels before 5.3. It also fails with `EINVAL` for special files, but
+    // that is not included, as it is returned for overlapping ranges as
+    // well. Callers only pass regular files instead.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn is_copy_unsupported(err: &io::Error) -> bool {
+        matches!(
+            err.raw_os_error(),
+            Some(libc::ENOSYS) | Some(libc::EXDEV) | Some(libc::EOPNOTSUPP) | Some(libc::EPERM)
+        )
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn copy_file_range(
+        src: &File,
+        src_offset: u64,
+        dst: &File,
+        dst_offset: u64,
+        len: usize,
+    ) -> io::Result<usize> {
+        let mut src_offset = cvt_offset(src_offset)? as libc::loff_t;
+        let mut dst_offset = cvt_offset(dst_offset)? as libc::loff_t;
+        let ret = unsafe {
+            libc::syscall(
+                libc::SYS_copy_file_range,
+                src.as_raw_fd(),
+                &mut src_offset as *mut libc::loff_t,
+                dst.as_raw_fd(),
+                &mut dst_offset as *mut libc::loff_t,
+                len,
+                0 as libc::c_uint,
+            )
+        };
+        cvt(ret as libc::ssize_t).map_err(|e| {
+            if is_copy_unsupported(&e) {
+                io::Error::new(io::ErrorKind::Unsupported, e)
+            } else {
+                e
+            }
+        })
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    pub fn copy_file_range(
+        _src: &File,
+        _src_offset: u64,
+        _dst: &File,
+        _dst_offset: u64,
+        _len: usize,
+    ) -> io::Result<usize> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "copy_file_range is not supported on this platform",
+        ))
+    }
+
+    // Returns the device and inode numbers of the file, which identify it.
+    pub fn file_id(metadata: &Metadata) -> Option<(u64, u64)> {
+        Some((metadata.dev(), metadata.ino()))
+    }
+
+    pub fn is_pipe(fd: BorrowedFd) -> io::Result<bool> {
+        let mut stat: libc::stat = unsafe { mem::zeroed() };
+        if unsafe { libc::fstat(fd.as_raw_fd(), &mut stat) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(stat.st_mode & libc::S_IFMT == libc::S_IFIFO)
+    }
+
+    // `sendfile` and `splice` fail with these errors if they cannot be used
+    // for the given descriptors.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn is_send_unsupported(err: &io::Error) -> bool {
+        matches!(
+            err.raw_os_error(),
+            Some(libc::ENOSYS) | Some(libc::EINVAL) | Some(libc::EOPNOTSUPP)
+        )
+    }
+
+    // Sends up to `len` bytes of `file` at `offset` to `out`, using `splice`
+    // if `out` is a pipe and `sendfile` otherwise.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn send_file(
+        file: &File,
+        offset: u64,
+        out: BorrowedFd,
+        pipe: bool,
+        len: usize,
+    ) -> io::Result<usize> {
+        let mut offset = cvt_offset(offset)?;
+        let ret = unsafe {
+            if pipe {
+                let mut off = offset as libc::loff_t;
+                libc::splice(
+                    file.as_raw_fd(),
+                    &mut off,
+                    out.as_raw_fd(),
+                    ptr::null_mut(),
+                    len,
+                    libc::SPLICE_F_MOVE,
+                )
+            } else {
+                lfs::sendfile(out.as_raw_fd(), file.as_raw_fd(), &mut offset, len)
+            }
+        };
+        cvt(ret).map_err(|e| {
+            if is_send_unsupported(&e) {
+                io::Error::new(io::ErrorKind::Unsupported, e)
+            } else {
+                e
+            }
+        })
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    pub fn send_file(
+        _file: &File,
+        _offset: u64,
+        _out: BorrowedFd,
+        _pipe: bool,
+        _len: usize,
+    ) -> io::Result<usize> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "sendfile is not supported on this platform",
+        ))
+    }
+
+    pub fn write_fd(fd: BorrowedFd, buf: &[u8]) -> io::Result<usize> {
+        cvt(unsafe {
+            libc::write(
+                fd.as_raw_fd(),
+                buf.as_ptr() as *const libc::c_void,
+                cmp::min(buf.len(), READ_LIMIT),
+            )
+        })
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn fallocate(file: &File, op: Fallocate, offset: u64, len: u64) -> io::Result<()> {
+        let mode = match op {
+            Fallocate::Allocate => 0,
+            Fallocate::PunchHole => libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+            Fallocate::ZeroRange => libc::FALLOC_FL_ZERO_RANGE,
+            Fallocate::CollapseRange => libc::FALLOC_FL_COLLAPSE_RANGE,
+            Fallocate::InsertRange => libc::FALLOC_FL_INSERT_RANGE,
+        };
+        // The kernel rejects empty ranges with `EINVAL`.
+        if len == 0 {
+            return Ok(());
+        }
+        cvt_offset(space::checked_end(offset, len)?)?;
+        let offset = cvt_offset(offset)?;
+        let len = cvt_offset(len)?;
+        if unsafe { lfs::fallocate(file.as_raw_fd(), mode, offset, len) } < 0 {
+            let err = io::Error::last_os_error();
+            return Err(match err.raw_os_error() {
+                Some(libc::EOPNOTSUPP) | Some(libc::ENOSYS) => {
+                    io::Error::new(io::ErrorKind::Unsupported, err)
+                }
+                _ => err,
+            });
+        }
+        Ok(())
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    pub fn fallocate(_file: &File, _op: Fallocate, _offset: u64, _len: u64) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "fallocate is not supported on this platform",
+        ))
+    }
+
+    // Opens a new file description for the file underlying `file`, so that
+    // seeking it does not move the cursor of `file`. A descriptor obtained
+    // with `dup` would share the cursor.
+    //
+    // Only regular files are reopened, as opening FIFOs may block and
+    // opening devices may have side effects.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn reopen(file: &File) -> io::Result<File> {
+        if !file.metadata()?.is_file() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "only regular files can be reopened",
+            ));
+        }
+        File::open(format!("/proc/self/fd/{}", file.as_raw_fd()))
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    pub fn reopen(_file: &File) -> io::Result<File> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "reopening files is not supported on this platform",
+        ))
+    }
+
+    // Returns the result of seeking with `SEEK_DATA` or `SEEK_HOLE`, or
+    // `None` if there is no data or hole at or after `offset`.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn seek_extent(file: &File, offset: u64, data: bool) -> io::Result<Option<u64>> {
+        let whence = if data {
+            libc::SEEK_DATA
+        } else {
+            libc::SEEK_HOLE
+        };
+        let offset = cvt_offset(offset)?;
+        let result = unsafe { lfs::lseek(file.as_raw_fd(), offset, whence) };
+        if result < 0 {
+            let err = io::Error::last_os_error();
+            return match err.raw_os_error() {
+                Some(libc::ENXIO) => Ok(None),
+                Some(libc::EINVAL) => Err(io::Error::new(io::ErrorKind::Unsupported, err)),
+                _ => Err(err),
+            };
+        }
+        Ok(Some(result as u64))
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    pub fn seek_extent(_file: &File, _offset: u64, _data: bool) -> io::Result<Option<u64>> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "SEEK_DATA and SEEK_HOLE are not supported on this platform",
+        ))
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    mod fiemap {
+        // The definitions of `linux/fiemap.h`, which are not provided by
+        // `libc`.
+        #[repr(C)]
+        pub struct Fiemap {
+            pub fm_start: u64,
+            pub fm_length: u64,
+            pub fm_flags: u32,
+            pub fm_mapped_extents: u32,
+            pub fm_extent_count: u32,
+            pub fm_reserved: u32,
+        }
+
+        #[repr(C)]
+        #[derive(Clone, Copy)]
+        pub struct FiemapExtent {
+            pub fe_logical: u64,
+            pub fe_physical: u64,
+            pub fe_length: u64,
+            pub fe_reserved64: [u64; 2],
+            pub fe_flags: u32,
+            pub fe_reserved: [u32; 3],
+        }
+
+        // `_IOWR('f', 11, struct fiemap)`
+        pub const FS_IOC_FIEMAP: u32 = 0xc020_660b;
+
+        pub const FIEMAP_EXTENT_LAST: u32 = 0x0000_0001;
+        pub const FIEMAP_EXTENT_UNKNOWN: u32 = 0x0000_0002;
+        pub const FIEMAP_EXTENT_DELALLOC: u32 = 0x0000_0004;
+        pub const FIEMAP_EXTENT_ENCODED: u32 = 0x0000_0008;
+        pub const FIEMAP_EXTENT_DATA_ENCRYPTED: u32 = 0x0000_0080;
+        pub const FIEMAP_EXTENT_NOT_ALIGNED: u32 = 0x0000_0100;
+        pub const FIEMAP_EXTENT_DATA_INLINE: u32 = 0x0000_0200;
+        pub const FIEMAP_EXTENT_DATA_TAIL: u32 = 0x0000_0400;
+        pub const FIEMAP_EXTENT_UNWRITTEN: u32 = 0x0000_0800;
+        pub const FIEMAP_EXTENT_MERGED: u32 = 0x0000_1000;
+        pub const FIEMAP_EXTENT_SHARED: u32 = 0x0000_2000;
+    }
+
+    // The number of extents requested by a single `FS_IOC_FIEMAP` call.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    const FIEMAP_EXTENTS: usize = 64;
+
+    // Appends the extents overlapping `[offset, offset + len)` to `extents`,
+    // up to `FIEMAP_EXTENTS` of them. Returns `true` if the last extent of
+    // the file was reached.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn fiemap(
+        file: &File,
+        offset: u64,
+        len: u64,
+        extents: &mut Vec<Extent>,
+    ) -> io::Result<bool> {
+        use self::fiemap::*;
+        use ExtentFlags;
+
+        #[repr(C)]
+        struct Request {
+            header: Fiemap,
+            extents: [FiemapExtent; FIEMAP_EXTENTS],
+        }
+
+        const FLAGS: [(u32, ExtentFlags); 10] = [
+            (FIEMAP_EXTENT_UNKNOWN, ExtentFlags::UNKNOWN),
+            (FIEMAP_EXTENT_DELALLOC, ExtentFlags::DELALLOC),
+            (FIEMAP_EXTENT_ENCODED, ExtentFlags::ENCODED),
+            (FIEMAP_EXTENT_DATA_ENCRYPTED, ExtentFlags::ENCRYPTED),
+            (FIEMAP_EXTENT_NOT_ALIGNED, ExtentFlags::NOT_ALIGNED),
+            (FIEMAP_EXTENT_DATA_INLINE, ExtentFlags::INLINE),
+            (FIEMAP_EXTENT_DATA_TAIL, ExtentFlags::TAIL),
+            (FIEMAP_EXTENT_UNWRITTEN, ExtentFlags::UNWRITTEN),
+            (FIEMAP_EXTENT_MERGED, ExtentFlags::MERGED),
+            (FIEMAP_EXTENT_SHARED, ExtentFlags::SHARED),
+        ];
+
+        let mut request: Request = unsafe { mem::zeroed() };
+        request.header.fm_start = offset;
+        request.header.fm_length = len;
+        request.header.fm_extent_count = FIEMAP_EXTENTS as u32;
+        let result = unsafe {
+            libc::ioctl(
+                file.as_raw_fd(),
+                FS_IOC_FIEMAP as _,
+                &mut request as *mut Request,
+            )
+        };
+        if result < 0 {
+            let err = io::Error::last_os_error();
+            return Err(match err.raw_os_error() {
+                Some(libc::EOPNOTSUPP) | Some(libc::ENOTTY) => {
+                    io::Error::new(io::ErrorKind::Unsupported, err)
+                }
+                _ => err,
+            });
+        }
+        let mapped = cmp::min(request.header.fm_mapped_extents as usize, FIEMAP_EXTENTS);
+        let mut last = false;
+        for extent in &request.extents[..mapped] {
+            let mut flags = ExtentFlags::empty();
+            for &(bit, flag) in FLAGS.iter() {
+                if extent.fe_flags & bit != 0 {
+                    flags |= flag;
+                }
+            }
+            last |= extent.fe_flags & FIEMAP_EXTENT_LAST != 0;
+            extents.push(Extent {
+                logical: extent.fe_logical,
+                physical: extent.fe_physical,
+                len: extent.fe_length,
+                flags,
+            });
+        }
+        Ok(last)
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    pub fn fiemap(
+        _file: &File,
+        _offset: u64,
+        _len: u64,
+        _extents: &mut Vec<Extent>,
+    ) -> io::Result<bool> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "FS_IOC_FIEMAP is not supported on this platform",
+        ))
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+    pub fn advise(file: &File, offset: u64, len: u64, advice: Advice) -> io::Result<()> {
+        let advice = match advice {
+            Advice::Normal => libc::POSIX_FADV_NORMAL,
+            Advice::Sequential => libc::POSIX_FADV_SEQUENTIAL,
+            Advice::Random => libc::POSIX_FADV_RANDOM,
+            Advice::WillNeed => libc::POSIX_FADV_WILLNEED,
+            Advice::DontNeed => libc::POSIX_FADV_DONTNEED,
+            Advice::NoReuse => libc::POSIX_FADV_NOREUSE,
+        };
+        let offset = cvt_offset(offset)?;
+        // Ranges extending past the largest offset are clamped.
+        let len = cmp::min(len, off_t::MAX as u64) as off_t;
+        // `posix_fadvise` returns the error instead of setting `errno`.
+        match unsafe { lfs::posix_fadvise(file.as_raw_fd(), offset, len, advice) } {
+            0 => Ok(()),
+            err => Err(io::Error::from_raw_os_error(err)),
+        }
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "android", target_os = "freebsd")))]
+    pub fn advise(_file: &File, _offset: u64, _len: u64, _advice: Advice) -> io::Result<()> {
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn readahead(file: &File, offset: u64, len: u64) -> io::Result<()> {
+        let offset = cvt_offset(offset)?;
+        let len = cmp::min(len, usize::MAX as u64) as usize;
+        if unsafe { libc::readahead(file.as_raw_fd(), offset, len) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn readahead(file: &File, offset: u64, len: u64) -> io::Result<()> {
+        // A zero length means the whole rest of the file to `advise`.
+        if len == 0 {
+            return Ok(());
+        }
+        advise(file, offset, len, Advice::WillNeed)
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn set_direct(options: &mut OpenOptions) -> io::Result<()> {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.custom_flags(libc::O_DIRECT);
+        Ok(())
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    pub fn set_direct(_options: &mut OpenOptions) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "O_DIRECT is not supported on this platform",
+        ))
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn direct_io_alignment(file: &File) -> io::Result<Alignment> {
+        let mut stx: libc::statx = unsafe { mem::zeroed() };
+        let result = unsafe {
+            libc::statx(
+                file.as_raw_fd(),
+                b"\0".as_ptr() as *const libc::c_char,
+                libc::AT_EMPTY_PATH,
+                libc::STATX_DIOALIGN,
+                &mut stx,
+            )
+        };
+        if result < 0 {
+            let err = io::Error::last_os_error();
+            // Kernels before 4.11 lack `statx`.
+            if !matches!(err.raw_os_error(), Some(libc::ENOSYS) | Some(libc::EPERM)) {
+                return Err(err);
+            }
+        } else if stx.stx_mask & libc::STATX_DIOALIGN != 0 {
+            if stx.stx_dio_offset_align == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "direct I/O is not supported for this file",
+                ));
+            }
+            return Ok(Alignment {
+                memory: stx.stx_dio_mem_align as usize,
+                offset: stx.stx_dio_offset_align as usize,
+            });
+        }
+
+        // Kernels before 6.1 do not report the alignment, so use the logical
+        // block size of the underlying block device instead.
+        let mut stat: libc::stat = unsafe { mem::zeroed() };
+        if unsafe { libc::fstat(file.as_raw_fd(), &mut stat) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let block_size = if stat.st_mode & libc::S_IFMT == libc::S_IFBLK {
+            let mut size: libc::c_int = 0;
+            if unsafe { libc::ioctl(file.as_raw_fd(), libc::BLKSSZGET, &mut size) } < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            size as usize
+        } else {
+            logical_block_size(stat.st_dev)?
+        };
+        Ok(Alignment {
+            memory: block_size,
+            offset: block_size,
+        })
+    }
+
+    // Reads the logical block size of the device `dev` from sysfs. For
+    // partitions, the queue parameters are found in the parent device.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn logical_block_size(dev: libc::dev_t) -> io::Result<usize> {
+        let dir = format!("/sys/dev/block/{}:{}", libc::major(dev), libc::minor(dev));
+        for path in ["queue/logical_block_size", "../queue/logical_block_size"].iter() {
+            if let Ok(contents) = ::std::fs::read_to_string(format!("{}/{}", dir, path)) {
+                if let Ok(size) = contents.trim().parse() {
+                    return Ok(size);
+                }
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "cannot determine the direct I/O alignment of this file",
+        ))
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    pub fn direct_io_alignment(_file: &File) -> io::Result<Alignment> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "direct I/O is not supported on this platform",
+        ))
+    }
+
+    // Sets (`Some`) or releases (`None`) a lock on a byte range, waiting for
+    // conflicting locks to be released if `wait` is set.
+    pub fn lock_range(
+        file: &File,
+        offset: u64,
+        len: u64,
+        kind: Option<LockKind>,
+        wait: bool,
+    ) -> io::Result<()> {
+        let mut lock: lfs::flock = unsafe { mem::zeroed() };
+        lock.l_type = match kind {
+            Some(LockKind::Shared) => libc::F_RDLCK,
+            Some(LockKind::Exclusive) => libc::F_WRLCK,
+            None => libc::F_UNLCK,
+        } as _;
+        lock.l_whence = libc::SEEK_SET as _;
+        lock.l_start = cvt_offset(offset)?;
+        lock.l_len = cvt_offset(len)?;
+        loop {
+            let err = match fcntl_lock(file, &lock, wait) {
+                Ok(()) => return Ok(()),
+                Err(err) => err,
+            };
+            match err.raw_os_error() {
+                Some(libc::EINTR) => {}
+                Some(libc::EAGAIN) | Some(libc::EACCES) => {
+                    return Err(io::Error::new(io::ErrorKind::WouldBlock, err))
+                }
+                _ => return Err(err),
+            }
+        }
+    }
+
+    // Uses open file description locks, which belong to the file handle
+    // rather than the process. Kernels before 3.15 reject them with
+    // `EINVAL`, in which case process-associated locks are used instead.
+    #[cfg(target_os = "linux")]
+    fn fcntl_lock(file: &File, lock: &lfs::flock, wait: bool) -> io::Result<()> {
+        let cmd = if wait {
+            libc::F_OFD_SETLKW
+        } else {
+            libc::F_OFD_SETLK
+        };
+        if unsafe { lfs::fcntl(file.as_raw_fd(), cmd, lock) } == 0 {
+            return Ok(());
+        }
+        let err = io::Error::last_os_error();
+        if err.raw_os_error() != Some(libc::EINVAL) {
+            return Err(err);
+        }
+        posix_lock(file, lock, wait)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn fcntl_lock(file: &File, lock: &lfs::flock, wait: bool) -> io::Result<()> {
+        posix_lock(file, lock, wait)
+    }
+
+    fn posix_lock(file: &File, lock: &lfs::flock, wait: bool) -> io::Result<()> {
+        let cmd = if wait { lfs::F_SETLKW } else { lfs::F_SETLK };
+        if unsafe { lfs::fcntl(file.as_raw_fd(), cmd, lock) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
 }
 
 #[cfg(windows)]
 mod windows {
-    use std::fs::File;
+    use std::fs::{File, Metadata, OpenOptions};
     use std::io;
+    use std::io::{IoSlice, IoSliceMut};
     use std::os::windows::fs::FileExt;
 
+    use super::Fallocate;
+    use {Advice, Alignment, Extent, LockKind, ReadBufCursor, RwFlags};
+
     #[inline]
     pub fn read_offset(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
         file.seek_read(buf, offset)
@@ -36,4 +944,177 @@ mod windows {
     pub fn write_offset(file: &File, buf: &[u8], offset: u64) -> io::Result<usize> {
         file.seek_write(buf, offset)
     }
+
+    pub fn read_vectored_offset(
+        file: &File,
+        bufs: &mut [IoSliceMut],
+        offset: u64,
+    ) -> io::Result<usize> {
+        let buf = bufs
+            .iter_mut()
+            .find(|b| !b.is_empty())
+            .map_or(&mut [][..], |b| &mut **b);
+        read_offset(file, buf, offset)
+    }
+
+    pub fn write_vectored_offset(file: &File, bufs: &[IoSlice], offset: u64) -> io::Result<usize> {
+        let buf = bufs
+            .iter()
+            .find(|b| !b.is_empty())
+            .map_or(&[][..], |b| &**b);
+        write_offset(file, buf, offset)
+    }
+
+    pub fn read_buf_offset(file: &File, mut buf: ReadBufCursor, offset: u64) -> io::Result<()> {
+        let n = read_offset(file, buf.initialize_unfilled(), offset)?;
+        buf.advance(n);
+        Ok(())
+    }
+
+    pub fn read_offset_with_flags(
+        file: &File,
+        buf: &mut [u8],
+        offset: u64,
+        flags: RwFlags,
+    ) -> io::Result<usize> {
+        if !flags.is_empty() {
+            return Err(::flags::unsupported());
+        }
+        read_offset(file, buf, offset)
+    }
+
+    pub fn write_offset_with_flags(
+        file: &File,
+        buf: &[u8],
+        offset: u64,
+        flags: RwFlags,
+    ) -> io::Result<usize> {
+        if !flags.is_empty() {
+            return Err(::flags::unsupported());
+        }
+        write_offset(file, buf, offset)
+    }
+
+    pub fn copy_file_range(
+        _src: &File,
+        _src_offset: u64,
+        _dst: &File,
+        _dst_offset: u64,
+        _len: usize,
+    ) -> io::Result<usize> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "copy_file_range is not supported on this platform",
+        ))
+    }
+
+    // The file index is not exposed by stable `std`, so files cannot be
+    // identified.
+    pub fn file_id(_metadata: &Metadata) -> Option<(u64, u64)> {
+        None
+    }
+
+    pub fn fallocate(_file: &File, _op: Fallocate, _offset: u64, _len: u64) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "fallocate is not supported on this platform",
+        ))
+    }
+
+    pub fn reopen(_file: &File) -> io::Result<File> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "reopening files is not supported on this platform",
+        ))
+    }
+
+    pub fn seek_extent(_file: &File, _offset: u64, _data: bool) -> io::Result<Option<u64>> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "SEEK_DATA and SEEK_HOLE are not supported on this platform",
+        ))
+    }
+
+    pub fn fiemap(
+        _file: &File,
+        _offset: u64,
+        _len: u64,
+        _extents: &mut Vec<Extent>,
+    ) -> io::Result<bool> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "FS_IOC_FIEMAP is not supported on this platform",
+        ))
+    }
+
+    pub fn advise(_file: &File, _offset: u64, _len: u64, _advice: Advice) -> io::Result<()> {
+        Ok(())
+    }
+
+    pub fn readahead(_file: &File, _offset: u64, _len: u64) -> io::Result<()> {
+        Ok(())
+    }
+
+    pub fn set_direct(_options: &mut OpenOptions) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "O_DIRECT is not supported on this platform",
+        ))
+    }
+
+    pub fn direct_io_alignment(_file: &File) -> io::Result<Alignment> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "direct I/O is not supported on this platform",
+        ))
+    }
+
+    pub fn lock_range(
+        _file: &File,
+        _offset: u64,
+        _len: u64,
+        _kind: Option<LockKind>,
+        _wait: bool,
+    ) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "byte-range locks are not supported on this platform",
+        ))
+    }
+}
+
+#[cfg(all(test, any(target_os = "linux", target_os = "android")))]
+mod tests {
+    use std::env;
+    use std::fs::File;
+    use std::io;
+
+    use super::{fallocate, reopen, Fallocate};
+    use test_util::temp_file;
+
+    #[test]
+    fn fallocate_ranges() {
+        let file = temp_file(b"");
+        for &op in &[
+            Fallocate::Allocate,
+            Fallocate::PunchHole,
+            Fallocate::ZeroRange,
+        ] {
+            fallocate(&file, op, 1 << 20, 0).unwrap();
+            let err = fallocate(&file, op, u64::MAX, 1).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+            let err = fallocate(&file, op, i64::MAX as u64, 1).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+        assert_eq!(file.metadata().unwrap().len(), 0);
+    }
+
+    #[test]
+    fn reopen_regular_files_only() {
+        let file = File::open("/dev/null").unwrap();
+        let err = reopen(&file).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+        let file = File::open(env::current_exe().unwrap()).unwrap();
+        reopen(&file).unwrap();
+    }
 }
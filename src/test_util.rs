@@ -0,0 +1,28 @@
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use WriteAt;
+
+/// Creates an anonymous temporary file holding `contents`.
+///
+/// The file is removed from the file system right away and disappears once
+/// it is closed.
+pub fn temp_file(contents: &[u8]) -> File {
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+    let path = env::temp_dir().join(format!(
+        "file_offset-test-{}-{}",
+        process::id(),
+        COUNT.fetch_add(1, Ordering::Relaxed)
+    ));
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&path)
+        .unwrap();
+    fs::remove_file(&path).unwrap();
+    file.write_all_offset(contents, 0).unwrap();
+    file
+}
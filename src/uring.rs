@@ -0,0 +1,860 @@
+use io_uring::{opcode, squeue, types, IoUring, Probe};
+use libc;
+use std::cmp;
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io;
+use std::io::{IoSlice, IoSliceMut};
+use std::mem;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::slice;
+use std::sync::{Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+
+use {lock_ignoring_poison, Advice, ReadAt, ReadBufCursor, RwFlags, WriteAt};
+
+/// The default number of submission queue entries of the ring.
+const DEFAULT_ENTRIES: u32 = 128;
+
+// User data of entries submitted by the blocking APIs have this bit set, the
+// lower bits contain the index of the entry in the batch. Entries submitted
+// with owned buffers use tokens without this bit.
+const BATCH_BIT: u64 = 1 << 63;
+
+// The user data of requests cancelling operations in flight, whose
+// completions are ignored.
+const CANCEL_DATA: u64 = u64::MAX;
+
+// How long to wait before retrying if the kernel cannot accept entries and
+// there are no completions to make room.
+const BACKOFF: Duration = Duration::from_millis(1);
+
+/// A file performing positioned reads and writes through an `io_uring`.
+///
+/// Batches of reads or writes are submitted to the kernel with a single
+/// system call using `read_batch` and `write_batch`. Operations on owned
+/// buffers can also be submitted without waiting for them, using
+/// `submit_read` and `submit_write`, and their completions collected later
+/// using `poll` or `wait`. Buffers registered with `register_buffers` can be
+/// used with `read_fixed` and `write_fixed`, saving the kernel from mapping
+/// them for every operation. The file itself is registered with the ring if
+/// possible.
+///
+/// If the kernel does not support `io_uring` or its read and write
+/// operations (added in Linux 5.6), or its use is disabled, all operations
+/// fall back to `pread` and `pwrite`. The same happens if the ring fails
+/// unexpectedly, after waiting for the operations in flight. `is_uring`
+/// reports which backend is in use.
+///
+/// `UringFile` also implements `ReadAt` and `WriteAt`, with
+/// `read_offsets_batch` and `write_offsets_batch` going through the ring. As
+/// a single operation gains nothing from going through the ring, the other
+/// methods use `pread` and `pwrite` directly.
+///
+/// Requires the `io_uring` feature and is only available on Linux.
+#[derive(Debug)]
+pub struct UringFile {
+    file: File,
+    ring: Mutex<Ring>,
+}
+
+/// A completed operation submitted with `UringFile::submit_read` or
+/// `UringFile::submit_write`.
+#[derive(Debug)]
+pub struct Completion {
+    /// The token returned when the operation was submitted.
+    pub token: u64,
+    /// The number of bytes read or written, or the error that occurred.
+    pub result: io::Result<usize>,
+    /// The buffer passed when the operation was submitted.
+    pub buf: Vec<u8>,
+}
+
+struct Ring {
+    // `None` if the kernel lacks `io_uring` or after an unrecoverable error.
+    uring: Option<IoUring>,
+    fd: RawFd,
+    fixed_file: bool,
+    buffers: Vec<Vec<u8>>,
+    pending: HashMap<u64, Pending>,
+    completed: VecDeque<Completion>,
+    batch: Vec<Option<io::Result<usize>>>,
+    next_token: u64,
+    // Entries pushed to the submission queue but not yet submitted.
+    unsubmitted: usize,
+    // Entries submitted to the kernel whose completion was not yet reaped.
+    in_kernel: usize,
+}
+
+struct Pending {
+    buf: Vec<u8>,
+    offset: u64,
+    write: bool,
+}
+
+impl UringFile {
+    /// Creates a new `UringFile` with a ring of a default size.
+    pub fn new(file: File) -> io::Result<UringFile> {
+        UringFile::with_entries(file, DEFAULT_ENTRIES)
+    }
+
+    /// Creates a new `UringFile` whose submission queue has the given number
+    /// of entries.
+    ///
+    /// Batches larger than the submission queue are submitted in several
+    /// steps. If `io_uring` is unavailable, the returned file uses `pread`
+    /// and `pwrite` instead.
+    pub fn with_entries(file: File, entries: u32) -> io::Result<UringFile> {
+        let uring = match IoUring::new(entries) {
+            Ok(ref uring) if !supports_read_write(uring) => None,
+            Ok(uring) => Some(uring),
+            Err(ref e) if is_unavailable(e) => None,
+            Err(e) => return Err(e),
+        };
+        Ok(UringFile::from_ring(file, uring))
+    }
+
+    // Creates a `UringFile` using `uring`, or `pread` and `pwrite` if it is
+    // `None`.
+    fn from_ring(file: File, uring: Option<IoUring>) -> UringFile {
+        let fd = file.as_raw_fd();
+        let fixed_file = match uring {
+            Some(ref uring) => uring.submitter().register_files(&[fd]).is_ok(),
+            None => false,
+        };
+        UringFile {
+            file,
+            ring: Mutex::new(Ring {
+                uring,
+                fd,
+                fixed_file,
+                buffers: Vec::new(),
+                pending: HashMap::new(),
+                completed: VecDeque::new(),
+                batch: Vec::new(),
+                next_token: 0,
+                unsubmitted: 0,
+                in_kernel: 0,
+            }),
+        }
+    }
+
+    /// Returns `true` if operations are performed using `io_uring`, and
+    /// `false` if they fall back to `pread` and `pwrite`.
+    pub fn is_uring(&self) -> bool {
+        self.lock().uring.is_some()
+    }
+
+    /// Returns a reference to the underlying file.
+    pub fn get_ref(&self) -> &File {
+        &self.file
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Ring> {
+        lock_ignoring_poison(&self.ring)
+    }
+
+    /// Reads into each buffer at its offset, submitting all reads at once.
+    ///
+    /// Blocks until all reads have completed and returns the result of each
+    /// read, in the same order as `reqs`. As with `read_offset`, reads may
+    /// be short.
+    pub fn read_batch(&self, reqs: &mut [(u64, &mut [u8])]) -> Vec<io::Result<usize>> {
+        let mut ring = self.lock();
+        let entries = reqs
+            .iter_mut()
+            .map(|&mut (offset, ref mut buf)| ring.read_entry(buf.as_mut_ptr(), buf.len(), offset))
+            .collect();
+        ring.run(&self.file, entries, |i| {
+            let (offset, ref mut buf) = reqs[i];
+            self.file.read_offset(buf, offset)
+        })
+    }
+
+    /// Writes each buffer at its offset, submitting all writes at once.
+    ///
+    /// Blocks until all writes have completed and returns the result of
+    /// each write, in the same order as `reqs`. As with `write_offset`,
+    /// writes may be short. The writes are not ordered with respect to each
+    /// other.
+    pub fn write_batch(&self, reqs: &[(u64, &[u8])]) -> Vec<io::Result<usize>> {
+        let mut ring = self.lock();
+        let entries = reqs
+            .iter()
+            .map(|&(offset, buf)| ring.write_entry(buf.as_ptr(), buf.len(), offset))
+            .collect();
+        ring.run(&self.file, entries, |i| {
+            let (offset, buf) = reqs[i];
+            self.file.write_offset(buf, offset)
+        })
+    }
+
+    /// Registers buffers with the ring for use with `read_fixed` and
+    /// `write_fixed`.
+    ///
+    /// Buffers can only be registered once. Their contents can be accessed
+    /// using `with_fixed_buffer`.
+    pub fn register_buffers(&self, buffers: Vec<Vec<u8>>) -> io::Result<()> {
+        let mut ring = self.lock();
+        if !ring.buffers.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "buffers are already registered",
+            ));
+        }
+        if let Some(ref uring) = ring.uring {
+            let iovecs: Vec<libc::iovec> = buffers
+                .iter()
+                .map(|b| libc::iovec {
+                    iov_base: b.as_ptr() as *mut libc::c_void,
+                    iov_len: b.len(),
+                })
+                .collect();
+            // The buffers are only ever accessed as slices while registered
+            // and thus never move.
+            unsafe {
+                uring.submitter().register_buffers(&iovecs)?;
+            }
+        }
+        ring.buffers = buffers;
+        Ok(())
+    }
+
+    /// Calls `f` with the contents of the registered buffer `index`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no buffer with the given index is registered.
+    pub fn with_fixed_buffer<F, R>(&self, index: usize, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        f(&mut self.lock().buffers[index])
+    }
+
+    /// Reads up to `len` bytes at the given offset into the start of the
+    /// registered buffer `index`.
+    ///
+    /// Returns the number of bytes read. Blocks until the read has
+    /// completed.
+    pub fn read_fixed(&self, index: usize, len: usize, offset: u64) -> io::Result<usize> {
+        let mut ring = self.lock();
+        let ptr = ring.fixed_buffer(index, len)?.as_mut_ptr();
+        let entry = ring.read_fixed_entry(ptr, len, index, offset);
+        let file = &self.file;
+        let mut result = ring.run(file, vec![entry], |_| {
+            let buf = unsafe { slice::from_raw_parts_mut(ptr, len) };
+            file.read_offset(buf, offset)
+        });
+        result.pop().unwrap()
+    }
+
+    /// Writes the first `len` bytes of the registered buffer `index` at the
+    /// given offset.
+    ///
+    /// Returns the number of bytes written. Blocks until the write has
+    /// completed.
+    pub fn write_fixed(&self, index: usize, len: usize, offset: u64) -> io::Result<usize> {
+        let mut ring = self.lock();
+        let ptr = ring.fixed_buffer(index, len)?.as_ptr();
+        let entry = ring.write_fixed_entry(ptr, len, index, offset);
+        let file = &self.file;
+        let mut result = ring.run(file, vec![entry], |_| {
+            let buf = unsafe { slice::from_raw_parts(ptr, len) };
+            file.write_offset(buf, offset)
+        });
+        result.pop().unwrap()
+    }
+
+    /// Submits a read into `buf` at the given offset without waiting for it
+    /// to complete.
+    ///
+    /// Up to `buf.len()` bytes are read. Returns a token identifying the
+    /// operation in the `Completion` returned by `poll` or `wait`, which also
+    /// hands back the buffer.
+    pub fn submit_read(&self, buf: Vec<u8>, offset: u64) -> io::Result<u64> {
+        self.lock().submit(&self.file, buf, offset, false)
+    }
+
+    /// Submits a write of `buf` at the given offset without waiting for it
+    /// to complete.
+    ///
+    /// Returns a token identifying the operation in the `Completion`
+    /// returned by `poll` or `wait`, which also hands back the buffer.
+    pub fn submit_write(&self, buf: Vec<u8>, offset: u64) -> io::Result<u64> {
+        self.lock().submit(&self.file, buf, offset, true)
+    }
+
+    /// Returns the operations submitted with `submit_read` or
+    /// `submit_write` that have completed, without blocking.
+    pub fn poll(&self) -> Vec<Completion> {
+        let mut ring = self.lock();
+        if ring.enter(0).is_err() {
+            ring.fail(&self.file);
+        }
+        ring.reap();
+        ring.completed.drain(..).collect()
+    }
+
+    /// Returns the operations submitted with `submit_read` or
+    /// `submit_write` that have completed, blocking until there is at least
+    /// one.
+    ///
+    /// Returns an empty vector if there are no outstanding operations.
+    pub fn wait(&self) -> Vec<Completion> {
+        let mut ring = self.lock();
+        while ring.completed.is_empty() && !ring.pending.is_empty() {
+            if ring.enter(1).is_err() {
+                ring.fail(&self.file);
+            }
+            ring.reap();
+        }
+        ring.completed.drain(..).collect()
+    }
+}
+
+impl Ring {
+    fn target(&self) -> Target {
+        if self.fixed_file {
+            Target::Fixed(types::Fixed(0))
+        } else {
+            Target::Fd(types::Fd(self.fd))
+        }
+    }
+
+    fn read_entry(&self, ptr: *mut u8, len: usize, offset: u64) -> squeue::Entry {
+        let len = clamp_len(len);
+        match self.target() {
+            Target::Fixed(fd) => opcode::Read::new(fd, ptr, len).offset(offset).build(),
+            Target::Fd(fd) => opcode::Read::new(fd, ptr, len).offset(offset).build(),
+        }
+    }
+
+    fn write_entry(&self, ptr: *const u8, len: usize, offset: u64) -> squeue::Entry {
+        let len = clamp_len(len);
+        match self.target() {
+            Target::Fixed(fd) => opcode::Write::new(fd, ptr, len).offset(offset).build(),
+            Target::Fd(fd) => opcode::Write::new(fd, ptr, len).offset(offset).build(),
+        }
+    }
+
+    fn read_fixed_entry(&self, ptr: *mut u8, len: usize, index: usize, off: u64) -> squeue::Entry {
+        let (len, index) = (clamp_len(len), index as u16);
+        match self.target() {
+            Target::Fixed(fd) => opcode::ReadFixed::new(fd, ptr, len, index)
+                .offset(off)
+                .build(),
+            Target::Fd(fd) => opcode::ReadFixed::new(fd, ptr, len, index)
+                .offset(off)
+                .build(),
+        }
+    }
+
+    fn write_fixed_entry(
+        &self,
+        ptr: *const u8,
+        len: usize,
+        index: usize,
+        off: u64,
+    ) -> squeue::Entry {
+        let (len, index) = (clamp_len(len), index as u16);
+        match self.target() {
+            Target::Fixed(fd) => opcode::WriteFixed::new(fd, ptr, len, index)
+                .offset(off)
+                .build(),
+            Target::Fd(fd) => opcode::WriteFixed::new(fd, ptr, len, index)
+                .offset(off)
+                .build(),
+        }
+    }
+
+    fn fixed_buffer(&mut self, index: usize, len: usize) -> io::Result<&mut [u8]> {
+        match self.buffers.get_mut(index) {
+            Some(buf) if len <= buf.len() => Ok(&mut buf[..len]),
+            Some(_) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "length exceeds the registered buffer",
+            )),
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "no registered buffer with this index",
+            )),
+        }
+    }
+
+    // Submits `entries` and waits for all of them to complete, returning
+    // their results in order. If the ring is unavailable or fails, the
+    // remaining entries are performed synchronously using `fallback`.
+    //
+    // The entries may point to borrowed memory, which is fine as this
+    // function does not return before the kernel is done with all of them.
+    fn run<F>(
+        &mut self,
+        file: &File,
+        entries: Vec<squeue::Entry>,
+        mut fallback: F,
+    ) -> Vec<io::Result<usize>>
+    where
+        F: FnMut(usize) -> io::Result<usize>,
+    {
+        let count = entries.len();
+        self.batch = (0..count).map(|_| None).collect();
+        let mut next = 0;
+        let mut completed = 0;
+        while self.uring.is_some() && completed < count {
+            {
+                let uring = self.uring.as_mut().unwrap();
+                let mut sq = uring.submission();
+                while next < count && !sq.is_full() {
+                    let entry = entries[next].clone().user_data(BATCH_BIT | next as u64);
+                    unsafe {
+                        sq.push(&entry).expect("submission queue is full");
+                    }
+                    next += 1;
+                    self.unsubmitted += 1;
+                }
+            }
+            if self.enter(1).is_err() {
+                self.fail(file);
+                break;
+            }
+            self.reap();
+            completed = self.batch.iter().filter(|r| r.is_some()).count();
+        }
+        let batch = mem::take(&mut self.batch);
+        batch
+            .into_iter()
+            .enumerate()
+            .map(|(i, r)| r.unwrap_or_else(|| fallback(i)))
+            .collect()
+    }
+
+    fn submit(
+        &mut self,
+        file: &File,
+        mut buf: Vec<u8>,
+        offset: u64,
+        write: bool,
+    ) -> io::Result<u64> {
+        let token = self.next_token;
+        self.next_token = (self.next_token + 1) & !BATCH_BIT;
+        if self.uring.is_none() {
+            let result = if write {
+                file.write_offset(&buf, offset)
+            } else {
+                file.read_offset(&mut buf, offset)
+            };
+            self.completed.push_back(Completion { token, result, buf });
+            return Ok(token);
+        }
+        let entry = if write {
+            self.write_entry(buf.as_ptr(), buf.len(), offset)
+        } else {
+            self.read_entry(buf.as_mut_ptr(), buf.len(), offset)
+        };
+        // Make room in the submission queue if necessary.
+        if self.uring.as_mut().unwrap().submission().is_full() && self.enter(0).is_err() {
+            self.fail(file);
+            return self.submit(file, buf, offset, write);
+        }
+        unsafe {
+            self.uring
+                .as_mut()
+                .unwrap()
+                .submission()
+                .push(&entry.user_data(token))
+                .expect("submission queue is full");
+        }
+        self.unsubmitted += 1;
+        // The buffer's heap memory does not move when the `Vec` is moved.
+        self.pending.insert(token, Pending { buf, offset, write });
+        if self.enter(0).is_err() {
+            self.fail(file);
+        }
+        Ok(token)
+    }
+
+    // Submits all pushed entries and waits for at least `want` completions.
+    //
+    // If the kernel cannot accept more entries until completions are
+    // reaped (`EBUSY`) or temporarily lacks resources (`EAGAIN`),
+    // completions are reaped before trying again. Reaping enough of them
+    // satisfies the wait.
+    fn enter(&mut self, want: usize) -> io::Result<()> {
+        loop {
+            let result = match self.uring {
+                Some(ref uring) => uring.submit_and_wait(want),
+                None => return Ok(()),
+            };
+            match result {
+                Ok(n) => {
+                    self.unsubmitted -= n;
+                    self.in_kernel += n;
+                    return Ok(());
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(ref e)
+                    if matches!(e.raw_os_error(), Some(libc::EBUSY) | Some(libc::EAGAIN)) =>
+                {
+                    let reaped = self.reap();
+                    if want > 0 && reaped >= want {
+                        return Ok(());
+                    }
+                    if reaped == 0 {
+                        thread::sleep(BACKOFF);
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    // Processes the available completions, returning their number.
+    //
+    // Operations that were cancelled by `fail` are left pending, so that
+    // they are performed synchronously.
+    fn reap(&mut self) -> usize {
+        let uring = match self.uring {
+            Some(ref mut uring) => uring,
+            None => return 0,
+        };
+        let mut reaped = 0;
+        for cqe in uring.completion() {
+            self.in_kernel -= 1;
+            reaped += 1;
+            let user_data = cqe.user_data();
+            if user_data == CANCEL_DATA || cqe.result() == -libc::ECANCELED {
+                continue;
+            }
+            let result = if cqe.result() < 0 {
+                Err(io::Error::from_raw_os_error(-cqe.result()))
+            } else {
+                Ok(cqe.result() as usize)
+            };
+            if user_data & BATCH_BIT != 0 {
+                self.batch[(user_data & !BATCH_BIT) as usize] = Some(result);
+            } else if let Some(p) = self.pending.remove(&user_data) {
+                self.completed.push_back(Completion {
+                    token: user_data,
+                    result,
+                    buf: p.buf,
+                });
+            }
+        }
+        reaped
+    }
+
+    // Requests the cancellation of all operations in flight, as far as the
+    // submission queue has room.
+    fn cancel_in_flight(&mut self) {
+        let targets: Vec<u64> = self
+            .batch
+            .iter()
+            .enumerate()
+            .filter(|&(_, r)| r.is_none())
+            .map(|(i, _)| BATCH_BIT | i as u64)
+            .chain(self.pending.keys().cloned())
+            .collect();
+        let uring = match self.uring {
+            Some(ref mut uring) => uring,
+            None => return,
+        };
+        let mut sq = uring.submission();
+        for user_data in targets {
+            let entry = opcode::AsyncCancel::new(user_data)
+                .build()
+                .user_data(CANCEL_DATA);
+            if unsafe { sq.push(&entry) }.is_err() {
+                break;
+            }
+            self.unsubmitted += 1;
+        }
+    }
+
+    // Handles an unexpected error of the ring by falling back to
+    // synchronous I/O.
+    //
+    // Operations in flight may still access their buffers, so they are
+    // cancelled and waited for first. Those that did not complete are then
+    // performed synchronously, along with everything not yet submitted.
+    fn fail(&mut self, file: &File) {
+        self.cancel_in_flight();
+        while self.in_kernel + self.unsubmitted > 0 {
+            let result = match self.uring {
+                Some(ref uring) => uring.submit_and_wait(1),
+                None => break,
+            };
+            match result {
+                Ok(n) => {
+                    self.unsubmitted -= n;
+                    self.in_kernel += n;
+                }
+                // Completions are posted to the queue even if entering the
+                // kernel keeps failing.
+                Err(_) => {
+                    if self.in_kernel == 0 {
+                        break;
+                    }
+                    thread::sleep(BACKOFF);
+                }
+            }
+            self.reap();
+        }
+        // Unsubmitted entries are discarded along with the ring.
+        self.uring = None;
+        self.unsubmitted = 0;
+        let pending: Vec<_> = self.pending.drain().collect();
+        for (token, mut p) in pending {
+            let result = if p.write {
+                file.write_offset(&p.buf, p.offset)
+            } else {
+                file.read_offset(&mut p.buf, p.offset)
+            };
+            self.completed.push_back(Completion {
+                token,
+                result,
+                buf: p.buf,
+            });
+        }
+    }
+}
+
+impl Drop for UringFile {
+    fn drop(&mut self) {
+        // Wait for all operations using pending buffers to complete before
+        // the buffers and the file are dropped.
+        let ring = self.ring.get_mut().unwrap_or_else(|e| e.into_inner());
+        while ring.uring.is_some() && ring.in_kernel + ring.unsubmitted > 0 {
+            if ring.enter(1).is_err() {
+                ring.fail(&self.file);
+            }
+            ring.reap();
+        }
+    }
+}
+
+impl ::std::fmt::Debug for Ring {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        f.debug_struct("Ring")
+            .field("uring", &self.uring.is_some())
+            .field("fixed_file", &self.fixed_file)
+            .field("buffers", &self.buffers.len())
+            .field("pending", &self.pending.len())
+            .finish()
+    }
+}
+
+enum Target {
+    Fixed(types::Fixed),
+    Fd(types::Fd),
+}
+
+fn clamp_len(len: usize) -> u32 {
+    cmp::min(len, u32::MAX as usize) as u32
+}
+
+// `IORING_OP_READ` and `IORING_OP_WRITE` were added in Linux 5.6, along
+// with probing for supported operations. Earlier kernels support `io_uring`,
+// but fail every read and write with `EINVAL`.
+fn supports_read_write(uring: &IoUring) -> bool {
+    let mut probe = Probe::new();
+    uring.submitter().register_probe(&mut probe).is_ok()
+        && probe.is_supported(opcode::Read::CODE)
+        && probe.is_supported(opcode::Write::CODE)
+}
+
+fn is_unavailable(err: &io::Error) -> bool {
+    matches!(
+        err.raw_os_error(),
+        Some(libc::ENOSYS) | Some(libc::EPERM) | Some(libc::EACCES)
+    )
+}
+
+impl ReadAt for UringFile {
+    #[inline]
+    fn read_offset(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        self.file.read_offset(buf, offset)
+    }
+    #[inline]
+    fn size(&self) -> io::Result<u64> {
+        self.file.size()
+    }
+    #[inline]
+    fn read_offset_with_flags(
+        &self,
+        buf: &mut [u8],
+        offset: u64,
+        flags: RwFlags,
+    ) -> io::Result<usize> {
+        self.file.read_offset_with_flags(buf, offset, flags)
+    }
+    #[inline]
+    fn read_buf_offset(&self, buf: ReadBufCursor, offset: u64) -> io::Result<()> {
+        self.file.read_buf_offset(buf, offset)
+    }
+    #[inline]
+    fn read_vectored_offset(&self, bufs: &mut [IoSliceMut], offset: u64) -> io::Result<usize> {
+        self.file.read_vectored_offset(bufs, offset)
+    }
+    #[inline]
+    fn read_offsets_batch(&self, reqs: &mut [(u64, &mut [u8])]) -> Vec<io::Result<usize>> {
+        self.read_batch(reqs)
+    }
+    #[inline]
+    fn advise_range(&self, offset: u64, len: u64, advice: Advice) -> io::Result<()> {
+        self.file.advise_range(offset, len, advice)
+    }
+    #[inline]
+    fn readahead(&self, offset: u64, len: u64) -> io::Result<()> {
+        self.file.readahead(offset, len)
+    }
+}
+
+impl WriteAt for UringFile {
+    #[inline]
+    fn write_offset(&self, buf: &[u8], offset: u64) -> io::Result<usize> {
+        self.file.write_offset(buf, offset)
+    }
+    #[inline]
+    fn set_size(&self, size: u64) -> io::Result<()> {
+        self.file.set_size(size)
+    }
+    #[inline]
+    fn write_offset_with_flags(
+        &self,
+        buf: &[u8],
+        offset: u64,
+        flags: RwFlags,
+    ) -> io::Result<usize> {
+        self.file.write_offset_with_flags(buf, offset, flags)
+    }
+    #[inline]
+    fn write_vectored_offset(&self, bufs: &[IoSlice], offset: u64) -> io::Result<usize> {
+        self.file.write_vectored_offset(bufs, offset)
+    }
+    #[inline]
+    fn write_offsets_batch(&self, reqs: &[(u64, &[u8])]) -> Vec<io::Result<usize>> {
+        self.write_batch(reqs)
+    }
+    #[inline]
+    fn allocate_range(&self, offset: u64, len: u64) -> io::Result<()> {
+        self.file.allocate_range(offset, len)
+    }
+    #[inline]
+    fn punch_hole(&self, offset: u64, len: u64) -> io::Result<()> {
+        self.file.punch_hole(offset, len)
+    }
+    #[inline]
+    fn zero_range(&self, offset: u64, len: u64) -> io::Result<()> {
+        self.file.zero_range(offset, len)
+    }
+    #[inline]
+    fn collapse_range(&self, offset: u64, len: u64) -> io::Result<()> {
+        self.file.collapse_range(offset, len)
+    }
+    #[inline]
+    fn insert_range(&self, offset: u64, len: u64) -> io::Result<()> {
+        self.file.insert_range(offset, len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::UringFile;
+    use test_util::temp_file;
+
+    // Returns a file using the ring if available, and one using the
+    // fallback.
+    fn files(contents: &[u8]) -> Vec<UringFile> {
+        let fallback = UringFile::from_ring(temp_file(contents), None);
+        assert!(!fallback.is_uring());
+        vec![UringFile::new(temp_file(contents)).unwrap(), fallback]
+    }
+
+    #[test]
+    fn batch_read_write() {
+        for file in files(b"") {
+            let results = file.write_batch(&[(0, b"abc"), (6, b"ghi"), (3, b"def")]);
+            assert_eq!(
+                results.into_iter().map(|r| r.unwrap()).collect::<Vec<_>>(),
+                [3; 3]
+            );
+            let (mut a, mut b) = ([0; 4], [0; 5]);
+            let results = file.read_batch(&mut [(5, &mut a), (0, &mut b)]);
+            assert_eq!(
+                results.into_iter().map(|r| r.unwrap()).collect::<Vec<_>>(),
+                [4, 5]
+            );
+            assert_eq!(&a, b"fghi");
+            assert_eq!(&b, b"abcde");
+        }
+    }
+
+    #[test]
+    fn batch_larger_than_queue() {
+        let data: Vec<u8> = (0..64).collect();
+        let file = UringFile::with_entries(temp_file(&data), 4).unwrap();
+        let mut bufs = [[0; 2]; 32];
+        let mut reqs: Vec<(u64, &mut [u8])> = bufs
+            .iter_mut()
+            .enumerate()
+            .map(|(i, b)| (2 * i as u64, &mut b[..]))
+            .collect();
+        assert!(file
+            .read_batch(&mut reqs)
+            .iter()
+            .all(|r| r.as_ref().unwrap() == &2));
+        assert_eq!(bufs.concat(), data);
+    }
+
+    #[test]
+    fn short_reads_at_eof() {
+        for file in files(b"0123456789") {
+            let (mut a, mut b, mut c) = ([0; 4], [0; 4], [0; 4]);
+            let results = file.read_batch(&mut [(8, &mut a), (10, &mut b), (20, &mut c)]);
+            assert_eq!(
+                results.into_iter().map(|r| r.unwrap()).collect::<Vec<_>>(),
+                [2, 0, 0]
+            );
+            assert_eq!(&a[..2], b"89");
+        }
+    }
+
+    #[test]
+    fn submit_and_wait() {
+        for file in files(b"0123456789") {
+            let read = file.submit_read(vec![0; 4], 2).unwrap();
+            let write = file.submit_write(b"xy".to_vec(), 10).unwrap();
+            let mut completions = Vec::new();
+            while completions.len() < 2 {
+                completions.extend(file.wait());
+            }
+            assert!(file.wait().is_empty());
+            completions.sort_by_key(|c| c.token);
+            assert_eq!(completions[0].token, read);
+            assert_eq!(completions[0].result.as_ref().unwrap(), &4);
+            assert_eq!(completions[0].buf, b"2345");
+            assert_eq!(completions[1].token, write);
+            assert_eq!(completions[1].result.as_ref().unwrap(), &2);
+        }
+    }
+
+    #[test]
+    fn fail_with_operations_in_flight() {
+        let file = UringFile::new(temp_file(b"0123456789")).unwrap();
+        let tokens: Vec<u64> = (0..8)
+            .map(|i| file.submit_read(vec![0; 2], i).unwrap())
+            .collect();
+        file.lock().fail(&file.file);
+        assert!(!file.is_uring());
+        let mut completions = file.wait();
+        assert_eq!(completions.len(), tokens.len());
+        completions.sort_by_key(|c| c.token);
+        for (i, c) in completions.iter().enumerate() {
+            assert_eq!(c.token, tokens[i]);
+            assert_eq!(c.result.as_ref().unwrap(), &2);
+            assert_eq!(c.buf, &b"0123456789"[i..i + 2]);
+        }
+    }
+}